@@ -2,6 +2,7 @@
 //!
 //! [1]: ../trait.Backend.html
 
+use crate::database::validate_path;
 use crate::prelude::*;
 
 /// Store secrets in yaml format.
@@ -11,6 +12,11 @@ use crate::prelude::*;
 ///  - name
 ///  - secret (encrypted, base64)
 ///  - salt (base64)
+///  - cipher (if recorded by the encrypter)
+///  - description and tags (if any)
+///
+/// The integrity manifest is stored alongside, in a file named after the database with a
+/// `.manifest.yaml` extension (`secrets.manifest.yaml` for `secrets.yaml`.)
 ///
 /// This `Backend` will be selected by the binary if the given database path ends with `.yaml`.
 pub struct YAML {
@@ -37,7 +43,7 @@ impl Backend for YAML {
     fn load(&self, name: String) -> Result<Encrypted> {
         self.read().context("loading database")?.into_iter().find(|s| {
             s.name == name
-        }).map(|s| Ok(s)).unwrap_or_else(|| Err(Error::msg("secret not found")))
+        }).map(Ok).unwrap_or_else(|| Err(Error::msg("secret not found")))
     }
 
     fn remove(&mut self, name: String) -> Result<()> {
@@ -50,22 +56,34 @@ impl Backend for YAML {
     fn list(&self) -> Result<Vec<Encrypted>> {
         self.read()
     }
+
+    fn load_manifest(&self) -> Result<Option<Manifest>> {
+        read_manifest(&self.path.with_extension("manifest.yaml"))
+    }
+
+    fn store_manifest(&mut self, manifest: Manifest) -> Result<()> {
+        write_manifest(&self.path.with_extension("manifest.yaml"), manifest)
+    }
+
+    fn supports_manifest(&self) -> bool {
+        true
+    }
 }
 impl YAML {
     fn read(&self) -> Result<Vec<Encrypted>> {
-        Ok(::serde_yaml::from_str(&match ::std::fs::read_to_string(&self.path) {
+        ::serde_yaml::from_str(&match ::std::fs::read_to_string(&self.path) {
             Err(err) if err.kind() == ::std::io::ErrorKind::NotFound => Ok("[]".to_string()),
             Err(err) => Err(err),
             Ok(val) => Ok(val),
-        }.context("reading file")?).context("unmarshalling yaml")?)
+        }.context("reading file")?).context("unmarshalling yaml")
     }
 
     fn write(&mut self, secrets: Vec<Encrypted>) -> Result<()> {
         let mut f = ::std::fs::OpenOptions::new().write(true).create(true)
             .truncate(true).open(&self.path).context("opening file")?;
-        Ok(f.write_all(
+        f.write_all(
             ::serde_yaml::to_string(&secrets).context("marshalling to yaml")?.as_bytes()
-        ).context("writing to file")?)
+        ).context("writing to file")
     }
 }
 
@@ -76,11 +94,26 @@ impl YAML {
 ///
 ///  - secret (encrypted, base64)
 ///  - salt (base64)
+///  - cipher (if recorded by the encrypter)
+///  - description and tags (if any)
+///
+/// Hierarchical names map to nested folders: `prod/db/password` is stored in the
+/// `prod/db/password` sub-folder. A secret and the secrets nested under its name can coexist,
+/// which is why nested segments cannot be named after the files above (as in `prod/tags`.)
+/// Names cannot escape the main folder either: their segments cannot be `.` or `..`.
+///
+/// The integrity manifest is stored in a `.manifest.yaml` file at the root of the main folder.
 ///
 /// This `Backend` will be selected by the binary if the given database path has no extension.
 pub struct Folder {
     path: PathBuf,
 }
+
+/// The files of a secret in its folder.
+const FILES: [&str; 5] = ["secret", "salt", "cipher", "description", "tags"];
+/// The file of the integrity manifest in the main folder.
+const MANIFEST: &str = ".manifest.yaml";
+
 impl Folder {
     pub fn new(path: PathBuf) -> Self {
         Self { path }
@@ -88,40 +121,112 @@ impl Folder {
 }
 impl Backend for Folder {
     fn store(&mut self, encrypted: Encrypted) -> Result<()> {
+        Self::validate(&encrypted.name)?;
         self.ensure_root(&encrypted.name)?;
 
         let mut salt_f = ::std::fs::OpenOptions::new().write(true).create(true)
             .truncate(true).open(self.salt_path(&encrypted.name)).context("opening file")?;
         salt_f.write_all(encrypted.salt.as_bytes())?;
 
+        let root = self.path.join(&encrypted.name);
+        write_optional(&root.join("cipher"), &encrypted.cipher)?;
+        write_optional(&root.join("description"), &encrypted.description)?;
+        write_optional(&root.join("tags"), &encrypted.tags.join("\n"))?;
+
         let mut secret_f = ::std::fs::OpenOptions::new().write(true).create(true)
             .truncate(true).open(self.secret_path(&encrypted.name)).context("opening file")?;
         Ok(secret_f.write_all(encrypted.secret.as_bytes())?)
     }
 
     fn load(&self, name: String) -> Result<Encrypted> {
+        Self::validate(&name)?;
         let salt_path = self.salt_path(&name);
         let secret_path = self.secret_path(&name);
+        let root = self.path.join(&name);
         Ok(Encrypted{
-            name,
             secret: ::std::fs::read_to_string(secret_path).context("reading secret file")?,
             salt: ::std::fs::read_to_string(salt_path).context("reading salt file")?,
+            cipher: read_optional(&root.join("cipher")).context("reading cipher file")?,
+            description: read_optional(&root.join("description"))
+                .context("reading description file")?,
+            tags: read_optional(&root.join("tags")).context("reading tags file")?
+                .lines().map(str::to_owned).collect(),
+            name,
         })
     }
 
     fn remove(&mut self, name: String) -> Result<()> {
-        Ok(::std::fs::remove_dir_all(self.path.join(name))?)
+        Self::validate(&name)?;
+        let root = self.path.join(&name);
+        if !self.secret_path(&name).is_file() {
+            return Err(Error::msg("secret not found"));
+        }
+        for file in FILES {
+            write_optional(&root.join(file), "")?;
+        }
+
+        // Clean up the folders left empty, up to the main folder.
+        for dir in root.ancestors().take_while(|dir| *dir != self.path) {
+            match ::std::fs::remove_dir(dir) {
+                Ok(()) => continue,
+                Err(_) if dir.read_dir().map(|mut d| d.next().is_some()).unwrap_or(false) => break,
+                Err(err) => return Err(err).context("removing secret folder"),
+            }
+        }
+        Ok(())
     }
 
     fn list(&self) -> Result<Vec<Encrypted>> {
-        ::std::fs::read_dir(&self.path).context(
-            "listing secret files"
-        )?.collect::<Result<Vec<_>, _>>()?.into_iter().filter_map(|dir| {
-            dir.path().file_name().map(|fname| self.load(fname.to_str().unwrap().to_owned()))
-        }).collect::<Result<Vec<_>>>()
+        self.names(&self.path, "")?.into_iter().map(|name| self.load(name)).collect()
+    }
+
+    fn load_manifest(&self) -> Result<Option<Manifest>> {
+        read_manifest(&self.path.join(MANIFEST))
+    }
+
+    fn store_manifest(&mut self, manifest: Manifest) -> Result<()> {
+        ::std::fs::create_dir_all(&self.path)?;
+        write_manifest(&self.path.join(MANIFEST), manifest)
+    }
+
+    fn supports_manifest(&self) -> bool {
+        true
     }
 }
 impl Folder {
+    /// Check that `name` maps to a folder of its own, where no other file is stored.
+    fn validate(name: &str) -> Result<()> {
+        validate_path(name)?;
+        let mut segments = name.split('/');
+        match segments.next() == Some(MANIFEST) || segments.any(|s| FILES.contains(&s)) {
+            true => Err(Error::msg(format!("invalid secret name for a folder: {}", name))),
+            false => Ok(()),
+        }
+    }
+
+    /// Names of the secrets under `dir`, prefixed by `prefix`.
+    fn names(&self, dir: &::std::path::Path, prefix: &str) -> Result<Vec<String>> {
+        let entries = match ::std::fs::read_dir(dir) {
+            Err(err) if err.kind() == ::std::io::ErrorKind::NotFound => return Ok(vec![]),
+            res => res,
+        }.context("listing secret files")?;
+
+        let mut names = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            let fname = match path.file_name().and_then(|fname| fname.to_str()) {
+                Some(fname) if path.is_dir() => fname,
+                _ => continue,
+            };
+            let name = format!("{}{}", prefix, fname);
+            if path.join("secret").is_file() {
+                names.push(name.clone());
+            }
+            names.extend(self.names(&path, &format!("{}/", name))?);
+        }
+        Ok(names)
+    }
+
     fn ensure_root(&self, name: &str) -> Result<PathBuf> {
         let root_path = self.path.join(name);
         let root_md = match ::std::fs::metadata(&root_path) {
@@ -148,3 +253,42 @@ impl Folder {
         self.path.join(name).join("secret")
     }
 }
+
+/// Write `content` to `path`, or remove the file if there is no content.
+fn write_optional(path: &::std::path::Path, content: &str) -> Result<()> {
+    if content.is_empty() {
+        match ::std::fs::remove_file(path) {
+            Err(err) if err.kind() == ::std::io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }?;
+    } else {
+        let mut f = ::std::fs::OpenOptions::new().write(true).create(true)
+            .truncate(true).open(path).context("opening file")?;
+        f.write_all(content.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Read the content of `path`, empty if the file does not exist.
+fn read_optional(path: &::std::path::Path) -> Result<String> {
+    match ::std::fs::read_to_string(path) {
+        Err(err) if err.kind() == ::std::io::ErrorKind::NotFound => Ok(String::new()),
+        res => Ok(res?),
+    }
+}
+
+fn read_manifest(path: &::std::path::Path) -> Result<Option<Manifest>> {
+    match ::std::fs::read_to_string(path) {
+        Err(err) if err.kind() == ::std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context("reading manifest file"),
+        Ok(val) => Ok(Some(::serde_yaml::from_str(&val).context("unmarshalling manifest")?)),
+    }
+}
+
+fn write_manifest(path: &::std::path::Path, manifest: Manifest) -> Result<()> {
+    let mut f = ::std::fs::OpenOptions::new().write(true).create(true)
+        .truncate(true).open(path).context("opening manifest file")?;
+    f.write_all(
+        ::serde_yaml::to_string(&manifest).context("marshalling manifest")?.as_bytes()
+    ).context("writing manifest file")
+}
@@ -1,15 +1,25 @@
+use ::anyhow::Context;
 use ::clishe::prelude::*;
-use ::std::io::Write;
+use ::std::io::{Read, Write};
 
 commands! {
     #[clap(about = "Store provided secret under the provided name")]
     Store(self, db: &mut hips::Database) -> Result<()> {
-        db.store(hips::Secret{name: self.name, secret: self.secret})
+        db.store(hips::Secret{
+            name: self.name,
+            secret: self.secret,
+            description: self.description.unwrap_or_default(),
+            tags: self.tag,
+        })
     } struct {
         #[clap(help = "The name to store/hide the secret under")]
         name: String,
         #[clap(help = "The secret to store and hide")]
         secret: String,
+        #[clap(long, help = "A description of the secret, available to templates")]
+        description: Option<String>,
+        #[clap(long, help = "A tag for the secret, available to templates (repeatable)")]
+        tag: Vec<String>,
     },
 
     #[clap(about = "Retrieve secret under the provided name")]
@@ -23,13 +33,50 @@ commands! {
 
     #[clap(alias = "ls", about = "List all available secrets")]
     List(self, db: &mut hips::Database) -> Result<()> {
-        let mut names = db.list()?.into_iter().map(|secret| {
-            secret.name
-        }).collect::<Vec<String>>();
-        names.sort();
+        if self.scopes {
+            for scope in db.scopes() {
+                let status = if scope.unlocked { "unlocked" } else { "locked" };
+                let prefix = if scope.prefix.is_empty() { "(default)" } else { &scope.prefix };
+                writeln!(::std::io::stdout(), "{}\t{}", prefix, status)?;
+            }
+            return Ok(());
+        }
+        let names = db.list_names(self.scope.as_deref().unwrap_or_default())?;
         writeln!(::std::io::stdout(), "{}", names.join("\n"))?;
         Ok(())
-    } struct {},
+    } struct {
+        #[clap(help = "Only list the secrets under this scope, as in prod/")]
+        scope: Option<String>,
+        #[clap(long, help = "List the key scopes instead, and whether they can be unlocked")]
+        scopes: bool,
+    },
+
+    #[clap(about = "Find secrets by name, description, tags or value")]
+    Find(self, db: &mut hips::Database) -> Result<()> {
+        let pattern = self.pattern.as_deref().unwrap_or("*");
+        let mut pattern = match self.regex {
+            true => hips::Pattern::regex(pattern),
+            false => hips::Pattern::glob(pattern),
+        }?;
+        for tag in self.tag {
+            pattern = pattern.with_tag(tag);
+        }
+        if self.values {
+            pattern = pattern.with_values();
+        }
+        let names = db.find(&pattern)?;
+        writeln!(::std::io::stdout(), "{}", names.join("\n"))?;
+        Ok(())
+    } struct {
+        #[clap(help = "Glob matching names or descriptions, as in 'aws_*'")]
+        pattern: Option<String>,
+        #[clap(long, help = "Search for a regex instead of matching a glob")]
+        regex: bool,
+        #[clap(long, help = "Only find secrets with this tag (repeatable)")]
+        tag: Vec<String>,
+        #[clap(long, help = "Search inside the decrypted values as well")]
+        values: bool,
+    },
 
     #[clap(alias = "rm", about = "Remove the secret under the provided name")]
     Remove(self, db: &mut hips::Database) -> Result<()> {
@@ -39,11 +86,9 @@ commands! {
         name: String,
     },
 
-    #[clap(about = "Rename the secret to the provided name")]
+    #[clap(about = "Rename the secret, and the secrets under it, to the provided name")]
     Rename(self, db: &mut hips::Database) -> Result<()> {
-        let secret = db.load(self.current_name.clone())?;
-        db.store(hips::Secret{name: self.new_name, secret: secret.secret})?;
-        db.remove(self.current_name)
+        db.rename(&self.current_name, &self.new_name).map(|_| ())
     } struct {
         #[clap(help = "Current name of the secret to move")]
         current_name: String,
@@ -51,30 +96,517 @@ commands! {
         new_name: String,
     },
 
+    #[clap(about = "Run a command with the secrets of a scope as environment variables")]
+    Exec(self, db: &mut hips::Database) -> Result<()> {
+        let secrets = relative(db, &self.scope.unwrap_or_default())?.into_iter().map(|secret| {
+            (hips::exporters::env_var_name(&secret.name), secret.secret)
+        }).collect::<Vec<_>>();
+
+        let command = self.command;
+        let status = ::std::process::Command::new(&command[0])
+            .args(&command[1..])
+            .envs(secrets)
+            .status()
+            .with_context(|| format!("running {}", command[0]))?;
+        ::std::process::exit(status.code().unwrap_or(1))
+    } struct {
+        #[clap(long, help = "Only export the secrets under this scope, named relatively to it")]
+        scope: Option<String>,
+        #[clap(required = true, last = true, help = "The command to run, after --")]
+        command: Vec<String>,
+    },
+
     #[clap(alias = "rot", about = "Re-encrypt the whole database using a new password")]
     Rotate(self, db: &mut hips::Database) -> Result<()> {
-        let db_path = ::std::env::var("HIPS_DATABASE")?.into();
-        let mut new_db = hips::Database::from_file(db_path, self.new_password)?;
-        for secret in db.list()? {
-            new_db.store(secret)?;
+        // Migrations (to age, to key scopes...) read using the previous configuration.
+        let mut from = match self.from {
+            Some(vars) => Some(hips::Database::open(
+                crate::unwrap_env_var("HIPS_DATABASE")?.into(),
+                crate::encrypter(&vars, None, None)
+                    .with_context(|| format!("configuring {}", vars))?,
+            )?),
+            None => None,
+        };
+        let db = from.as_mut().unwrap_or(db);
+        let cipher = match self.cipher {
+            Some(cipher) => Some(cipher),
+            None => crate::database_cipher(db)?,
+        };
+        db.rotate(crate::encrypter("HIPS", self.new_password, cipher)?)
+    } struct {
+        #[clap(name = "new-password", help = "The password to re-encrypt the database with, if any")]
+        new_password: Option<String>,
+        #[clap(long, help = "The cipher to re-encrypt the database with, kept by default (ring)")]
+        cipher: Option<hips::encrypters::Cipher>,
+        #[clap(long, help = "Prefix of the variables configuring the current encryption, as in \
+            <FROM>_PASSWORD, if it differs from the HIPS_* ones")]
+        from: Option<String>,
+    },
+
+    #[clap(about = "Render a directory of *.tmpl and *.j2 templates into an output directory")]
+    Render(self, db: &mut hips::Database) -> Result<()> {
+        let mode = u32::from_str_radix(&self.mode, 8).context("parsing mode")?;
+        let changed = db.render(&self.src, &self.dest, mode, self.check)?;
+        for path in &changed {
+            writeln!(::std::io::stdout(), "{}", self.dest.join(path).display())?;
+        }
+        match self.check && !changed.is_empty() {
+            true => Err(Error::msg(format!("{} outputs are out of date", changed.len()))),
+            false => Ok(()),
+        }
+    } struct {
+        #[clap(help = "Directory containing the templates")]
+        src: ::std::path::PathBuf,
+        #[clap(help = "Directory to write the rendered templates to")]
+        dest: ::std::path::PathBuf,
+        #[clap(long, default_value = "600", help = "Permissions of the outputs, in octal")]
+        mode: String,
+        #[clap(long, help = "Only list the outputs which would change, failing if any")]
+        check: bool,
+    },
+
+    #[clap(about = "Verify the database against its integrity manifest")]
+    Verify(self, db: &mut hips::Database) -> Result<()> {
+        if self.accept {
+            db.reset_manifest()?;
+        }
+        let verification = db.verify()?;
+        writeln!(::std::io::stdout(), "{}", verification)?;
+        match (verification.revision, self.min_revision) {
+            _ if !verification.is_ok() => Err(Error::msg("integrity check failed")),
+            (Some(revision), Some(min)) if revision < min => Err(Error::msg(format!(
+                "revision {} is older than {}, the database was rolled back", revision, min,
+            ))),
+            (None, Some(_)) => Err(Error::msg("no manifest to check the revision against")),
+            _ => Ok(()),
         }
+    } struct {
+        #[clap(long, help = "Fail if the manifest's revision is lower than this one")]
+        min_revision: Option<u64>,
+        #[clap(long, help = "Accept the current content of the database, re-creating the manifest")]
+        accept: bool,
+    },
+
+    #[clap(about = "Encrypt secret names, hiding them from anyone without the password")]
+    Opaque(self, db: &mut hips::Database) -> Result<()> {
+        db.set_opaque_names(!self.off)
+    } struct {
+        #[clap(long, help = "Store secret names in the clear again")]
+        off: bool,
+    },
+
+    #[clap(about = "Import secrets from a dotenv, env, JSON, YAML or sops file, or a pass store")]
+    Import(self, db: &mut hips::Database) -> Result<()> {
+        let format = match self.format {
+            Some(format) => format,
+            None => hips::importers::Format::guess(&self.file)
+                .context("cannot guess the format from the file name, use --format")?,
+        };
+        let mut secrets = hips::importers::read(&self.file, format)
+            .with_context(|| format!("importing {}", self.file.display()))?;
+        let prefix = self.prefix.unwrap_or_default();
+        let prefix = prefix.trim_end_matches('/');
+        if !prefix.is_empty() {
+            for secret in &mut secrets {
+                secret.name = format!("{}/{}", prefix, secret.name);
+            }
+        }
+        let conflicts = match (self.overwrite, self.skip_existing) {
+            (true, true) => return Err(Error::msg("--overwrite conflicts with --skip-existing")),
+            (true, false) => hips::importers::Conflicts::Overwrite,
+            (false, true) => hips::importers::Conflicts::Skip,
+            (false, false) => hips::importers::Conflicts::Fail,
+        };
+
+        let dry_run = self.dry_run;
+        let summary = hips::importers::import(db, secrets, conflicts, dry_run)?;
+        let verb = |done: &'static str, planned: &'static str| match dry_run {
+            true => planned,
+            false => done,
+        };
+        for (verb, names) in &[
+            (verb("added", "would add"), &summary.added),
+            (verb("overwritten", "would overwrite"), &summary.overwritten),
+            (verb("skipped", "would skip"), &summary.skipped),
+        ] {
+            for name in names.iter() {
+                writeln!(::std::io::stdout(), "{}: {}", verb, name)?;
+            }
+        }
+        Ok(())
+    } struct {
+        #[clap(help = "The file to import, or the password store folder")]
+        file: ::std::path::PathBuf,
+        #[clap(long, help = "Format: dotenv, env, json, yaml, pass or sops (guessed by default)")]
+        format: Option<hips::importers::Format>,
+        #[clap(long, help = "A prefix to store the secrets under, as in prod for prod/NAME")]
+        prefix: Option<String>,
+        #[clap(long, help = "Replace the secrets which already exist")]
+        overwrite: bool,
+        #[clap(long, help = "Keep the secrets which already exist")]
+        skip_existing: bool,
+        #[clap(long, help = "Print what would be imported without storing anything")]
+        dry_run: bool,
+    },
+
+    #[clap(about = "Export secrets as a dotenv, docker env, JSON, YAML or Kubernetes Secret file")]
+    Export(self, db: &mut hips::Database) -> Result<()> {
+        let secrets = relative(db, &self.prefix.unwrap_or_default())?;
+        let content = hips::exporters::export(
+            &secrets, self.format, self.name.as_deref(), self.namespace.as_deref(),
+        )?;
+        write!(::std::io::stdout(), "{}", content)?;
+        Ok(())
+    } struct {
+        #[clap(long, help = "Format: dotenv, docker-env, json, yaml or k8s-secret")]
+        format: hips::exporters::Format,
+        #[clap(long, help = "Only export the secrets under this prefix, named relatively to it")]
+        prefix: Option<String>,
+        #[clap(long, help = "The name of the Kubernetes Secret (k8s-secret only)")]
+        name: Option<String>,
+        #[clap(long, help = "The namespace of the Kubernetes Secret (k8s-secret only)")]
+        namespace: Option<String>,
+    },
+
+    #[clap(about = "Answer a Terraform external data source query, read from stdin")]
+    TerraformData(self, db: &mut hips::Database) -> Result<()> {
+        let mut query = String::new();
+        ::std::io::stdin().read_to_string(&mut query).context("reading query")?;
+        writeln!(::std::io::stdout(), "{}", hips::integrations::terraform_data(db, &query)?)?;
+        Ok(())
+    } struct {},
+
+    #[clap(about = "Print AWS credentials for the SDKs' credential_process setting")]
+    AwsCredentials(self, db: &mut hips::Database) -> Result<()> {
+        let credentials = hips::integrations::aws_credentials(
+            db,
+            &self.access_key,
+            &self.secret_key,
+            self.session_token.as_deref(),
+            self.expiration.as_deref(),
+        )?;
+        writeln!(::std::io::stdout(), "{}", credentials)?;
         Ok(())
     } struct {
-        #[clap(name = "new-password", help = "The password to re-encrypt the database with")]
-        new_password: String,
+        #[clap(long, help = "The name of the secret holding the access key id")]
+        access_key: String,
+        #[clap(long, help = "The name of the secret holding the secret access key")]
+        secret_key: String,
+        #[clap(long, requires = "expiration", help = "The secret holding the session token, if any")]
+        session_token: Option<String>,
+        #[clap(long, help = "The secret holding the session token's expiration, in ISO 8601")]
+        expiration: Option<String>,
+    },
+
+    #[clap(about = "Get, store, erase or list registry credentials, as a docker credential helper")]
+    DockerCredential(self, db: &mut hips::Database) -> Result<()> {
+        let mut input = String::new();
+        ::std::io::stdin().read_to_string(&mut input).context("reading input")?;
+        // Docker reads errors from the standard output, and expects a specific one for misses.
+        match hips::integrations::docker_credential(db, &self.action, &input).and_then(|output| {
+            output.context(hips::integrations::DOCKER_NOT_FOUND)
+        }) {
+            Ok(output) => Ok(write!(::std::io::stdout(), "{}", output)?),
+            Err(err) => {
+                writeln!(::std::io::stdout(), "{:#}", err)?;
+                ::std::process::exit(1)
+            }
+        }
+    } struct {
+        #[clap(help = "The helper action: get, store, erase or list")]
+        action: String,
+    },
+
+    #[clap(about = "List the secrets added, removed or changed between two databases")]
+    Diff(self, _db: &mut hips::Database) -> Result<()> {
+        let tmp = ::tempfile::tempdir().context("creating temporary folder")?;
+        let a = checkout(&self.a, &tmp.path().join("a")).with_context(|| self.a.clone())?;
+        let b = checkout(&self.b, &tmp.path().join("b")).with_context(|| self.b.clone())?;
+        let other = match self.other {
+            None => crate::encrypter("HIPS", None, None)?,
+            Some(vars) => crate::configured_encrypter(
+                &vars, ::std::env::var(format!("{}_PASSWORD", vars)).ok(), None,
+            )?.with_context(|| format!("{}_PASSWORD", vars))?,
+        };
+        let (a, b) = (
+            hips::Database::open(a, crate::encrypter("HIPS", None, None)?)?,
+            hips::Database::open(b, other)?,
+        );
+        let changes = a.diff(&b)?;
+        // The comparison goes on regardless, versions predating the manifest have none.
+        for (db, name) in &[(&a, &self.a), (&b, &self.b)] {
+            match db.verify() {
+                Ok(verification) if verification.is_ok() => (),
+                Ok(verification) => eprintln!("warning: {}: {}", name, verification),
+                Err(err) => eprintln!("warning: {}: {:#}", name, err),
+            }
+        }
+
+        for change in changes {
+            let (sign, name, values) = match change {
+                hips::Change::Added(secret) => ("+", secret.name, vec![secret.secret]),
+                hips::Change::Removed(secret) => ("-", secret.name, vec![secret.secret]),
+                hips::Change::Changed(ours, theirs) => {
+                    ("~", ours.name, vec![ours.secret, theirs.secret])
+                }
+            };
+            match self.show_values {
+                true => writeln!(::std::io::stdout(), "{} {}: {}", sign, name, values.iter()
+                    .map(::serde_json::to_string).collect::<Result<Vec<_>, _>>()?.join(" -> "))?,
+                false => writeln!(::std::io::stdout(), "{} {}", sign, name)?,
+            }
+        }
+        Ok(())
+    } struct {
+        #[clap(help = "The database to compare from: a path, or git:REV:path")]
+        a: String,
+        #[clap(help = "The database to compare to: a path, or git:REV:path")]
+        b: String,
+        #[clap(long, help = "Variables prefix configuring the second database's key (as in scopes)")]
+        other: Option<String>,
+        #[clap(long, help = "Print the values of the secrets, not only their names")]
+        show_values: bool,
+    },
+
+    #[clap(about = "Register hips' git drivers for the database in the enclosing repository")]
+    GitSetup(self, _db: &mut hips::Database) -> Result<()> {
+        let db_path = ::std::fs::canonicalize(::std::env::var("HIPS_DATABASE")
+            .context("HIPS_DATABASE")?).context("locating database")?;
+        let git = |args: &[&str]| -> Result<String> {
+            let output = ::std::process::Command::new("git")
+                .current_dir(db_path.parent().context("locating database folder")?)
+                .args(args)
+                .output()
+                .context("running git")?;
+            match output.status.success() {
+                true => Ok(String::from_utf8(output.stdout)?.trim().to_owned()),
+                false => Err(Error::msg(String::from_utf8_lossy(&output.stderr).trim().to_owned())),
+            }
+        };
+
+        let root = ::std::path::PathBuf::from(git(&["rev-parse", "--show-toplevel"])?);
+        let relative = db_path.strip_prefix(&root).context("database is outside the repository")?;
+        git(&["config", "diff.hips.textconv", "hips git-textconv"])?;
+        let mut attributes = vec![];
+        git(&["config", "merge.hips.name", "hips secrets database"])?;
+        git(&["config", "merge.hips.driver", "hips git-merge-driver %O %A %B"])?;
+        if relative.extension().map(|ext| ext == "yaml").unwrap_or(false) {
+            attributes.push(format!("/{} merge=hips diff=hips", relative.display()));
+            // The manifest is merged along, and signed again using the database's key.
+            let manifest = relative.with_extension("manifest.yaml");
+            attributes.push(format!("/{} merge=hips diff=hips", manifest.display()));
+        } else {
+            // Secret files are ciphertexts, the manifest tells which of them changed.
+            attributes.push(format!("/{}/** -diff", relative.display()));
+            attributes.push(format!("/{}/.manifest.yaml merge=hips diff=hips", relative.display()));
+        }
+
+        let path = root.join(".gitattributes");
+        let mut content = match ::std::fs::read_to_string(&path) {
+            Err(err) if err.kind() == ::std::io::ErrorKind::NotFound => Ok(String::new()),
+            res => res,
+        }.context("reading .gitattributes")?;
+        for attribute in attributes {
+            if content.lines().any(|line| line == attribute) {
+                continue;
+            }
+            if !content.is_empty() && !content.ends_with('\n') {
+                content.push('\n');
+            }
+            content.push_str(&attribute);
+            content.push('\n');
+        }
+        ::std::fs::write(&path, content).context("writing .gitattributes")
+    } struct {},
+
+    #[clap(about = "Merge two versions of a YAML database, as a git merge driver")]
+    GitMergeDriver(self, db: &mut hips::Database) -> Result<()> {
+        let read = |path: &::std::path::Path| ::std::fs::read_to_string(path)
+            .with_context(|| format!("reading {}", path.display()));
+        let (base, ours, theirs) = (read(&self.base)?, read(&self.ours)?, read(&self.theirs)?);
+        // Databases are lists of secrets, manifests are mappings.
+        let content = [&ours, &theirs, &base].iter().find(|c| !c.trim().is_empty()).cloned();
+        let manifest = ::serde_yaml::from_str::<::serde_yaml::Value>(content.map_or("", |c| c))
+            .context("parsing yaml")?.is_mapping();
+        let merge = match manifest {
+            true => hips::git::merge_manifest(db, &base, &ours, &theirs)
+                .context("merging the manifest, which needs the database and its key")?,
+            false => hips::git::merge(&base, &ours, &theirs)?,
+        };
+        ::std::fs::write(&self.ours, merge.content).context("writing merged database")?;
+        for name in &merge.conflicts {
+            eprintln!("conflict: {} was changed on both sides, keeping ours", name);
+        }
+        match merge.conflicts.is_empty() {
+            true => Ok(()),
+            false => Err(Error::msg(format!("{} conflicting secrets", merge.conflicts.len()))),
+        }
+    } struct {
+        #[clap(help = "The common ancestor's version (%O)")]
+        base: ::std::path::PathBuf,
+        #[clap(help = "Our version, overwritten by the merge result (%A)")]
+        ours: ::std::path::PathBuf,
+        #[clap(help = "Their version (%B)")]
+        theirs: ::std::path::PathBuf,
+    },
+
+    #[clap(about = "Get, store or erase git credentials, as a git credential helper")]
+    GitCredential(self, db: &mut hips::Database) -> Result<()> {
+        let mut input = String::new();
+        ::std::io::stdin().read_to_string(&mut input).context("reading credential")?;
+        let output = hips::git::credential(db, &self.action, &input, &self.scheme)?;
+        write!(::std::io::stdout(), "{}", output)?;
+        Ok(())
+    } struct {
+        #[clap(help = "The helper action: get, store or erase")]
+        action: String,
+        #[clap(long, default_value = hips::git::CREDENTIAL_SCHEME, help = "The names of the secrets")]
+        scheme: String,
+    },
+
+    #[clap(about = "Print a diffable view of a database or manifest file, as a git textconv filter")]
+    GitTextconv(self, db: &mut hips::Database) -> Result<()> {
+        let content = ::std::fs::read_to_string(&self.file)
+            .with_context(|| format!("reading {}", self.file.display()))?;
+        let view = hips::git::textconv(db, &content, self.plaintext)?;
+        writeln!(::std::io::stdout(), "{}", view.trim_end_matches('\n'))?;
+        Ok(())
+    } struct {
+        #[clap(help = "The file to convert")]
+        file: ::std::path::PathBuf,
+        #[clap(long, help = "Print decrypted values instead of their fingerprints")]
+        plaintext: bool,
     },
 
     #[clap(alias = "tmp", about = "Print one or multiple secrets according to a template")]
     Template(self, db: &mut hips::Database) -> Result<()> {
+        let path = ::std::path::PathBuf::from(&self.template);
+        let engine = self.engine.as_deref().unwrap_or_else(|| {
+            match path.extension().map(|ext| ext == "j2").unwrap_or(false) {
+                true => "jinja",
+                false => "tinytemplate",
+            }
+        });
+        // Only tinytemplate strings passed inline have their escape sequences interpreted.
         let template = match ::std::fs::read_to_string(&self.template) {
-            Err(err) if err.kind() == ::std::io::ErrorKind::NotFound => Ok(self.template),
-            Err(err) => Err(err),
-            Ok(val) => Ok(val),
-        }?;
-        writeln!(::std::io::stdout(), "{}", db.template(template)?)?;
+            Err(err) if err.kind() == ::std::io::ErrorKind::NotFound => match engine {
+                "tinytemplate" => ::snailquote::unescape(&format!("\"{}\"", self.template))?,
+                _ => self.template,
+            },
+            Err(err) => return Err(err.into()),
+            Ok(val) => val,
+        };
+        let engine: Box<dyn hips::Engine> = match engine {
+            "jinja" => Box::new(hips::engines::Jinja::new(path.parent().map(Into::into))),
+            "tinytemplate" => Box::new(hips::engines::TinyTemplate),
+            _ => return Err(Error::msg(format!("unsupported engine: {}", engine))),
+        };
+        if self.strict || self.unused.is_some() {
+            for name in db.check_template(template.clone(), self.unused.as_deref())? {
+                eprintln!("warning: secret {} is not used by the template", name);
+            }
+        }
+        writeln!(::std::io::stdout(), "{}", db.template_with(engine.as_ref(), template)?)?;
         Ok(())
     } struct {
         #[clap(help = "Template or path to file containing the template")]
         template: String,
+        #[clap(long, help = "tinytemplate (default) or jinja, the default for *.j2 files")]
+        engine: Option<String>,
+        #[clap(long, help = "Fail if the template references secrets which do not exist")]
+        strict: bool,
+        #[clap(long, help = "Warn about secrets with this prefix the template does not use")]
+        unused: Option<String>,
+    },
+}
+
+dispatchers! {
+    #[clap(about = "Split the password into recovery shares, or rebuild it from them")]
+    Recovery(self, _: &mut hips::Database) -> Result<()> [
+        Split: RecoverySplit,
+        Combine: RecoveryCombine,
+    ],
+}
+
+commands! {
+    #[clap(about = "Split the password into printable shares")]
+    RecoverySplit(self, _db: &mut hips::Database) -> Result<()> {
+        let password = ::std::env::var("HIPS_PASSWORD").context("HIPS_PASSWORD")?;
+        let shares = hips::recovery::split(&password, self.threshold, self.shares)?;
+        writeln!(::std::io::stdout(), "{}", shares.join("\n"))?;
+        Ok(())
+    } struct {
+        #[clap(long, help = "Number of shares needed to rebuild the password")]
+        threshold: u8,
+        #[clap(long, help = "Number of shares to generate")]
+        shares: u8,
     },
+
+    #[clap(about = "Rebuild the password from the shares in HIPS_RECOVERY_SHARES")]
+    RecoveryCombine(self, db: &mut hips::Database) -> Result<()> {
+        match self.rotate {
+            Some(new_password) => Rotate {
+                new_password: Some(new_password),
+                cipher: None,
+                from: None,
+            }.run(db),
+            None => {
+                let password = crate::recovered_password()?.context("HIPS_RECOVERY_SHARES")?;
+                writeln!(::std::io::stdout(), "{}", password)?;
+                Ok(())
+            }
+        }
+    } struct {
+        #[clap(long, help = "Re-encrypt the database using this new password instead of printing")]
+        rotate: Option<String>,
+    },
+}
+
+/// The secrets under `scope`, named relatively to it: `prod/db/password` becomes `db/password`.
+fn relative(db: &hips::Database, scope: &str) -> Result<Vec<hips::Secret>> {
+    let scope = scope.trim_end_matches('/');
+    Ok(db.list_scope(scope)?.into_iter().map(|mut secret| {
+        secret.name = secret.name[scope.len()..].trim_start_matches('/').to_owned();
+        secret
+    }).collect())
+}
+
+/// The path of a database to diff: `side` itself, or `git:REV:path` checked out under `dir`.
+///
+/// As with `git show`, the path is relative to the repository root unless it starts with `./`.
+fn checkout(side: &str, dir: &::std::path::Path) -> Result<::std::path::PathBuf> {
+    let spec = match side.strip_prefix("git:") {
+        Some(spec) => spec,
+        None => return Ok(side.into()),
+    };
+    let (rev, path) = spec.split_once(':').context("expected git:REV:path")?;
+    let git = |args: &[&str]| -> Result<Vec<u8>> {
+        let output = ::std::process::Command::new("git").args(args).output()
+            .context("running git")?;
+        match output.status.success() {
+            true => Ok(output.stdout),
+            false => Err(Error::msg(String::from_utf8_lossy(&output.stderr).trim().to_owned())),
+        }
+    };
+    let write = |dest: &::std::path::Path, object: &str| -> Result<()> {
+        ::std::fs::create_dir_all(dest.parent().context("locating checkout folder")?)
+            .context("creating checkout folder")?;
+        ::std::fs::write(dest, git(&["show", object])?).context("writing checkout")
+    };
+
+    let dest = dir.join(::std::path::Path::new(path).file_name().unwrap_or_default());
+    if git(&["cat-file", "-t", spec])?.starts_with(b"tree") {
+        for file in String::from_utf8(git(&["ls-tree", "-r", "--name-only", spec])?)?.lines() {
+            write(&dest.join(file), &format!("{}/{}", spec.trim_end_matches('/'), file))?;
+        }
+    } else {
+        write(&dest, spec)?;
+        // YAML databases keep their manifest next to them, see hips::backends::YAML.
+        let manifest = ::std::path::Path::new(path).with_extension("manifest.yaml");
+        let manifest = format!("{}:{}", rev, manifest.to_str().context("locating manifest")?);
+        if git(&["cat-file", "-e", &manifest]).is_ok() {
+            write(&dest.with_extension("manifest.yaml"), &manifest)?;
+        }
+    }
+    Ok(dest)
 }
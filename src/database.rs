@@ -1,6 +1,8 @@
+use crate::manifest::{is_key, key_name, KEY_NAME};
 use crate::prelude::*;
-use ::std::convert::TryFrom;
+use ::std::collections::{BTreeSet, HashMap};
 use ::std::iter::FromIterator;
+use ::std::path::Path;
 
 impl Database {
     /// Instantiate a new `Database` with injected `Backend`/`Encrypter`.
@@ -14,15 +16,23 @@ impl Database {
     /// Instantiate a new `Database` from a file.
     ///
     /// This function will try to guess which database type is needed, and create the appropriate
-    /// backend based on that. This is used in the binary, which supports only the `Backend`s and
-    /// `Encrypter`s shipped with hips.
+    /// backend based on that. The secrets are encrypted using the [`Ring`][1] encrypter.
+    ///
+    /// [1]: encrypters/struct.Ring.html
     pub fn from_file(path: PathBuf, password: String) -> Result<Self> {
+        Self::open(path, Box::new(crate::encrypters::Ring::new(password)))
+    }
+
+    /// Instantiate a new `Database` from a file, using the provided `Encrypter`.
+    ///
+    /// The backend is guessed from the file the same way [`from_file`][1] does. This is used in
+    /// the binary, which supports only the `Backend`s and `Encrypter`s shipped with hips.
+    ///
+    /// [1]: struct.Database.html#method.from_file
+    pub fn open(path: PathBuf, encrypter: Box<dyn crate::Encrypter>) -> Result<Self> {
         if let Some(extension) = path.extension() {
             if extension == "yaml" {
-                Ok(Self::new(
-                    Box::new(crate::backends::YAML::new(path)),
-                    Box::new(crate::encrypters::Ring::new(password)),
-                ))
+                Ok(Self::new(Box::new(crate::backends::YAML::new(path)), encrypter))
             } else {
                 Err(Error::msg(format!(
                     "unsupported format: {}",
@@ -30,42 +40,558 @@ impl Database {
                 )))
             }
         } else {
-            Ok(Self::new(
-                Box::new(crate::backends::Folder::new(path)),
-                Box::new(crate::encrypters::Ring::new(password)),
-            ))
+            Ok(Self::new(Box::new(crate::backends::Folder::new(path)), encrypter))
         }
     }
 }
 
 impl Database {
     /// Store the provided secret.
+    ///
+    /// Names are hierarchical, their segments separated by `/` (as in `prod/db/password`.)
+    /// Segments cannot be empty, `.` or `..`, and `.manifest` is reserved.
     pub fn store(&mut self, secret: Secret) -> Result<()> {
-        let encrypted = self.e.encrypt(secret).context("encrypting secret")?;
-        self.b.store(encrypted).context("storing secret")
+        validate(&secret.name)?;
+        let mut manifest = self.manifest()?;
+        let encrypted = self.record(&mut manifest, secret)?;
+        self.b.store(encrypted).context("storing secret")?;
+        self.save_manifest(manifest)
     }
 
     /// Load the `name` secret.
     pub fn load(&self, name: String) -> Result<Secret> {
-        self.e
-            .decrypt(self.b.load(name).context("looking up name")?)
-            .context("decrypting secret")
+        validate(&name)?;
+        let manifest = self.check(&self.b.list().context("listing secrets")?)?;
+        let stored_name = match &manifest {
+            Some(manifest) => manifest.stored_name(&name)?,
+            None => name,
+        };
+        self.unseal(manifest.as_ref(), self.b.load(stored_name).context("looking up name")?)
     }
 
     /// Remove the `name` secret.
     pub fn remove(&mut self, name: String) -> Result<()> {
-        self.b.remove(name).context("removing secret")
+        validate(&name)?;
+        let mut manifest = self.manifest()?;
+        let name = manifest.stored_name(&name)?;
+        self.section(&mut manifest, &name).remove(&name);
+        self.b.remove(name).context("removing secret")?;
+        self.save_manifest(manifest)
     }
 
     /// List all secrets.
     pub fn list(&self) -> Result<Vec<Secret>> {
-        self.b
-            .list()
-            .context("listing secrets")?
+        self.select(None)
+    }
+
+    /// List the secrets under `scope`, as in `prod` or `prod/` for `prod/db/password`.
+    ///
+    /// Only the secrets in scope are decrypted. An empty scope covers the whole database.
+    pub fn list_scope(&self, scope: &str) -> Result<Vec<Secret>> {
+        let names = self.names()?.into_iter().filter(|name| in_scope(name, scope)).collect();
+        self.select(Some(&names))
+    }
+
+    /// List the names of the secrets under `scope`, sorted.
+    ///
+    /// Nothing is decrypted, unless names are opaque.
+    pub fn list_names(&self, scope: &str) -> Result<Vec<String>> {
+        Ok(self.names()?.into_iter().filter(|name| in_scope(name, scope)).collect())
+    }
+
+    /// Find the names of the secrets matching `pattern`, sorted.
+    ///
+    /// Names, descriptions and tags are matched without decrypting anything, unless names are
+    /// opaque. Values are only decrypted if the pattern asks for it.
+    pub fn find(&self, pattern: &Pattern) -> Result<Vec<String>> {
+        let encrypted = self.b.list().context("listing secrets")?;
+        let manifest = self.check(&encrypted)?;
+        let opaque = manifest.as_ref().map(Manifest::opaque).unwrap_or(false);
+
+        let mut found = vec![];
+        for encrypted in encrypted.into_iter().filter(|e| !is_key(&e.name)) {
+            let secret = match opaque {
+                true => self.unseal(manifest.as_ref(), encrypted.clone())?,
+                false => Secret {
+                    name: encrypted.name.clone(),
+                    secret: String::new(),
+                    description: encrypted.description.clone(),
+                    tags: encrypted.tags.clone(),
+                },
+            };
+            if pattern.matches_metadata(&secret) {
+                found.push(secret.name);
+            } else if pattern.values() && pattern.matches_tags(&secret) {
+                let secret = match opaque {
+                    true => secret,
+                    false => self.unseal(manifest.as_ref(), encrypted)?,
+                };
+                if pattern.matches_value(&secret) {
+                    found.push(secret.name);
+                }
+            }
+        }
+        found.sort();
+        Ok(found)
+    }
+
+    /// Compare the secrets of this database to those of `other`, sorted by name.
+    ///
+    /// Both databases are decrypted, each with its own `Encrypter`, so the same value encrypted
+    /// under different passwords is no change. Being read-only, the comparison does not verify
+    /// the databases against their manifests (past versions may predate them), see
+    /// [`verify`][1].
+    ///
+    /// [1]: #method.verify
+    pub fn diff(&self, other: &Database) -> Result<Vec<Change>> {
+        let mut ours = self.list_unverified()?.into_iter().map(|s| (s.name.clone(), s))
+            .collect::<::std::collections::BTreeMap<_, _>>();
+        let mut changes = vec![];
+        for theirs in other.list_unverified().context("listing the other database")? {
+            match ours.remove(&theirs.name) {
+                None => changes.push(Change::Added(theirs)),
+                Some(secret) if secret != theirs => changes.push(Change::Changed(secret, theirs)),
+                Some(_) => (),
+            }
+        }
+        changes.extend(ours.into_values().map(Change::Removed));
+        changes.sort_by(|a, b| a.name().cmp(b.name()));
+        Ok(changes)
+    }
+
+    /// The key scopes of the database's `Encrypter`, if it has any.
+    pub fn scopes(&self) -> Vec<Scope> {
+        self.e.scopes()
+    }
+
+    /// The cipher the database was last rotated or created with, if its `Encrypter` records one.
+    ///
+    /// This is the cipher of the integrity manifest's key, which is only re-encrypted on
+    /// [`rotate`][1]: storing secrets using another cipher does not change it.
+    ///
+    /// [1]: #method.rotate
+    pub fn cipher(&self) -> Result<Option<String>> {
+        let encrypted = self.b.list().context("listing secrets")?;
+        let key = encrypted.into_iter().find(|e| e.name == KEY_NAME);
+        Ok(key.map(|key| key.cipher).filter(|cipher| !cipher.is_empty()))
+    }
+
+    /// Rename the `from` secret, along with all the secrets under it, to `to`.
+    ///
+    /// For example, renaming `prod` to `production` moves `prod/db/password` to
+    /// `production/db/password`. Returns the number of secrets renamed.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<usize> {
+        let (from, to) = (from.trim_end_matches(SEPARATOR), to.trim_end_matches(SEPARATOR));
+        validate(to)?;
+        let names = self.list_names(from)?.into_iter().collect::<BTreeSet<_>>();
+        if names.is_empty() || from.is_empty() {
+            return Err(Error::msg(format!("no secret under {}", from)));
+        } else if from == to {
+            return Ok(names.len());
+        } else if in_scope(to, from) {
+            return Err(Error::msg(format!("cannot move {} under itself", from)));
+        }
+
+        let mut manifest = self.manifest()?;
+        for secret in self.select(Some(&names))? {
+            let previous = manifest.stored_name(&secret.name)?;
+            let name = format!("{}{}", to, &secret.name[from.len()..]);
+            let encrypted = self.record(&mut manifest, Secret { name, ..secret })?;
+            self.b.store(encrypted).context("storing secret")?;
+            self.section(&mut manifest, &previous).remove(&previous);
+            self.b.remove(previous).context("removing secret")?;
+        }
+        self.save_manifest(manifest)?;
+        Ok(names.len())
+    }
+
+    /// Re-encrypt all secrets using the provided `Encrypter`.
+    ///
+    /// Secrets are decrypted using the current encrypter, which may differ from the new one in
+    /// kind (ring to age...) or in key scopes. Secrets move to the manifest section of their new
+    /// scope, whose key is created if needed, and the sections of the scopes which no longer
+    /// exist are dropped. The integrity manifest's keys are re-encrypted as well, its revisions
+    /// increased.
+    pub fn rotate(&mut self, encrypter: Box<dyn crate::Encrypter>) -> Result<()> {
+        let secrets = self.list()?;
+        let mut manifest = self.manifest()?;
+        for secret in &secrets {
+            let name = manifest.stored_name(&secret.name)?;
+            self.section(&mut manifest, &name).remove(&name);
+        }
+
+        self.e = encrypter;
+        let scopes = self.e.scopes().into_iter().map(|scope| scope.prefix).collect::<Vec<_>>();
+        let dropped = manifest.sections().map(|(prefix, _)| prefix.to_owned()).filter(|prefix| {
+            !prefix.is_empty() && !scopes.contains(prefix)
+        }).collect::<Vec<_>>();
+        let keys = self.b.list().context("listing secrets")?.into_iter().map(|e| e.name)
+            .filter(|name| is_key(name)).collect::<BTreeSet<_>>();
+        for prefix in dropped {
+            manifest.remove_section(&prefix);
+            if keys.contains(&key_name(&prefix)) {
+                self.b.remove(key_name(&prefix)).context("removing manifest key")?;
+            }
+        }
+        for prefix in self.unlocked_scopes() {
+            let section = manifest.section_mut(&prefix);
+            section.remove(&key_name(&prefix));
+            if !section.unlocked() {
+                section.generate_key()?;
+            }
+        }
+        for secret in secrets {
+            let encrypted = self.record(&mut manifest, secret)?;
+            self.b.store(encrypted).context("storing secret")?;
+        }
+        self.save_manifest(manifest)
+    }
+
+    /// Verify the database against its integrity [`Manifest`][1].
+    ///
+    /// A database with secrets but no manifest (or no manifest key) fails verification. With key
+    /// scopes, only the sections of the manifest whose scope is unlocked are verified.
+    ///
+    /// [1]: struct.Manifest.html
+    pub fn verify(&self) -> Result<Verification> {
+        let encrypted = self.b.list().context("listing secrets")?;
+        let mut manifest = match self.load_manifest(&encrypted)? {
+            Some(manifest) => manifest,
+            None => return Ok(Verification::default()),
+        };
+        let verifications = self.unlock(&mut manifest, &encrypted, false)?;
+        Ok(verifications.into_iter().fold(Verification::default(), Verification::merge))
+    }
+
+    /// Re-create the integrity [`Manifest`][1] from the current content of the database.
+    ///
+    /// This accepts any change made to the database without going through this library (manual
+    /// edits, git merges, writers without the key...) and creates the manifest of databases
+    /// which predate them. Its revision carries on from the previous manifest. With key scopes,
+    /// only the sections of the manifest whose scope is unlocked are re-created.
+    ///
+    /// [1]: struct.Manifest.html
+    pub fn reset_manifest(&mut self) -> Result<()> {
+        if !self.b.supports_manifest() {
+            return Ok(());
+        }
+        let encrypted = self.b.list().context("listing secrets")?;
+        let mut manifest = self.b.load_manifest().context("loading manifest")?.unwrap_or_default();
+        let prefixes = self.unlocked_scopes();
+        for prefix in &prefixes {
+            let section = manifest.section_mut(prefix);
+            match encrypted.iter().find(|e| e.name == key_name(prefix)) {
+                Some(key) => section.unlock(self.e.as_ref(), key)?,
+                None => section.generate_key()?,
+            }
+            section.clear();
+        }
+        for encrypted in encrypted {
+            let prefix = self.route(&encrypted.name);
+            if !prefixes.contains(&prefix) {
+                continue;
+            }
+            // Secrets which cannot be decrypted are accepted too, without a fingerprint.
+            let secret = match is_key(&encrypted.name) {
+                true => None,
+                false => self.unseal(Some(&manifest), encrypted.clone()).ok(),
+            };
+            let section = manifest.section_mut(&prefix);
+            let fingerprint = secret.map(|s| section.fingerprint(&s)).transpose()?;
+            section.insert(&encrypted, fingerprint);
+        }
+        self.save_manifest(manifest)
+    }
+
+    /// Switch the database to opaque names, or back to names in the clear.
+    ///
+    /// With opaque names, secrets are stored under a keyed hash of their name, the name itself
+    /// being encrypted alongside the secret. Someone without access to the database cannot tell
+    /// what the secrets are, while `load` and `list` keep working as usual.
+    ///
+    /// The mode is recorded along with the integrity manifest's key, so the backend has to
+    /// support manifests. Key scopes route secrets by name, opaque names cannot be combined
+    /// with them.
+    pub fn set_opaque_names(&mut self, opaque: bool) -> Result<()> {
+        if !self.b.supports_manifest() {
+            return Err(Error::msg("opaque names need a backend supporting manifests"));
+        }
+        let secrets = self.list()?;
+        let mut manifest = self.manifest()?;
+        if manifest.opaque() == opaque {
+            return Ok(());
+        } else if !self.e.scopes().is_empty() {
+            return Err(Error::msg(OPAQUE_SCOPES));
+        }
+
+        let previous = self.b.list().context("listing secrets")?;
+        let previous = previous.into_iter().filter(|e| !is_key(&e.name)).collect::<Vec<_>>();
+        manifest.set_opaque(opaque);
+        manifest.remove(KEY_NAME);
+        for secret in secrets {
+            let encrypted = self.record(&mut manifest, secret)?;
+            self.b.store(encrypted).context("storing secret")?;
+        }
+        for encrypted in previous {
+            self.section(&mut manifest, &encrypted.name).remove(&encrypted.name);
+            self.b.remove(encrypted.name).context("removing secret")?;
+        }
+        self.save_manifest(manifest)
+    }
+}
+
+impl Database {
+    fn seal(&self, manifest: &Manifest, secret: Secret) -> Result<Encrypted> {
+        let secret = if manifest.opaque() {
+            // The secret would silently land in the default scope, whatever its name.
+            if !self.e.scopes().is_empty() {
+                return Err(Error::msg(OPAQUE_SCOPES));
+            }
+            Secret {
+                name: manifest.stored_name(&secret.name)?,
+                secret: ::serde_json::to_string(&secret)?,
+                ..Default::default()
+            }
+        } else {
+            secret
+        };
+        self.e.encrypt(secret).context("encrypting secret")
+    }
+
+    /// Encrypt `secret` and record it in the section of `manifest` covering it.
+    fn record(&self, manifest: &mut Manifest, secret: Secret) -> Result<Encrypted> {
+        let encrypted = self.seal(manifest, secret.clone())?;
+        let section = self.section(manifest, &encrypted.name);
+        let fingerprint = match section.unlocked() {
+            true => Some(section.fingerprint(&secret)?),
+            false => None,
+        };
+        section.insert(&encrypted, fingerprint);
+        Ok(encrypted)
+    }
+
+    pub(crate) fn unseal(
+        &self,
+        manifest: Option<&Manifest>,
+        encrypted: Encrypted,
+    ) -> Result<Secret> {
+        let secret = self.e.decrypt(encrypted).context("decrypting secret")?;
+        if manifest.map(Manifest::opaque).unwrap_or(false) {
+            ::serde_json::from_str(&secret.secret).context("unmarshalling opaque secret")
+        } else {
+            Ok(secret)
+        }
+    }
+
+    /// Decrypt the secrets among `names`, or all of them.
+    fn select(&self, names: Option<&BTreeSet<String>>) -> Result<Vec<Secret>> {
+        let encrypted = self.b.list().context("listing secrets")?;
+        let manifest = self.check(&encrypted)?;
+        let stored_names = match (names, &manifest) {
+            (Some(names), Some(manifest)) => Some(names.iter().map(|name| {
+                manifest.stored_name(name)
+            }).collect::<Result<BTreeSet<String>>>()?),
+            (names, _) => names.cloned(),
+        };
+        encrypted
             .into_iter()
-            .map(|s| self.e.decrypt(s))
+            .filter(|s| !is_key(&s.name))
+            .filter(|s| stored_names.as_ref().is_none_or(|names| names.contains(&s.name)))
+            .map(|s| self.unseal(manifest.as_ref(), s))
             .collect::<Result<Vec<Secret>>>()
     }
+
+    /// Decrypt all secrets without verifying the manifest, whose keys still tell whether names
+    /// are opaque.
+    fn list_unverified(&self) -> Result<Vec<Secret>> {
+        let encrypted = self.b.list().context("listing secrets")?;
+        let mut manifest = self.b.load_manifest().context("loading manifest")?.unwrap_or_default();
+        self.unlock_manifest(&mut manifest, &encrypted)?;
+        encrypted
+            .into_iter()
+            .filter(|s| !is_key(&s.name))
+            .map(|s| self.unseal(Some(&manifest), s))
+            .collect::<Result<Vec<Secret>>>()
+    }
+
+    /// Names of all secrets, which only requires decrypting anything with opaque names.
+    fn names(&self) -> Result<BTreeSet<String>> {
+        let encrypted = self.b.list().context("listing secrets")?;
+        match self.check(&encrypted)? {
+            Some(manifest) if manifest.opaque() => {
+                Ok(self.list()?.into_iter().map(|s| s.name).collect())
+            }
+            _ => Ok(encrypted.into_iter().map(|s| s.name).filter(|n| !is_key(n)).collect()),
+        }
+    }
+
+    /// The manifest of the database, `None` for empty databases which have none.
+    fn load_manifest(&self, encrypted: &[Encrypted]) -> Result<Option<Manifest>> {
+        if !self.b.supports_manifest() {
+            return Ok(None);
+        }
+        let keys = encrypted.iter().any(|e| is_key(&e.name));
+        match (self.b.load_manifest().context("loading manifest")?, keys) {
+            (Some(manifest), true) => Ok(Some(manifest)),
+            (None, false) if encrypted.is_empty() => Ok(None),
+            (Some(_), false) => Err(Error::msg("the integrity manifest's key is missing")),
+            (None, _) => Err(Error::msg("the integrity manifest is missing")),
+        }.context("verifying integrity manifest")
+    }
+
+    /// Unlock and verify the sections of the manifest whose scope is unlocked.
+    ///
+    /// A scope with secrets and no section, or a section without its key, fails. Sections are
+    /// created (along with their key) for the scopes which have neither if `create` is set, in
+    /// which case write-only encrypters leave the existing sections locked.
+    fn unlock(
+        &self,
+        manifest: &mut Manifest,
+        encrypted: &[Encrypted],
+        create: bool,
+    ) -> Result<Vec<Verification>> {
+        let mut verifications = vec![];
+        for prefix in self.unlocked_scopes() {
+            let missing = |what: &str| Error::msg(match prefix.as_str() {
+                "" => format!("the integrity manifest{} is missing", what),
+                p => format!("the integrity manifest{} of scope '{}' is missing", what, p),
+            });
+            let entries = encrypted.iter().filter(|e| self.route(&e.name) == prefix).cloned()
+                .collect::<Vec<_>>();
+            let section = manifest.section_mut(&prefix);
+            match encrypted.iter().find(|e| e.name == key_name(&prefix)) {
+                Some(_) if section.exists() && create && self.e.write_only() => (),
+                Some(key) if section.exists() => {
+                    section.unlock(self.e.as_ref(), key)?;
+                    verifications.push(section.verify(&entries)?);
+                }
+                None if section.exists() => return Err(missing("'s key")),
+                None if entries.is_empty() => if create {
+                    section.generate_key()?;
+                },
+                _ => return Err(missing("")),
+            }
+        }
+        Ok(verifications)
+    }
+
+    /// The verified manifest of the database, `None` if it has none.
+    fn check(&self, encrypted: &[Encrypted]) -> Result<Option<Manifest>> {
+        let mut manifest = match self.load_manifest(encrypted)? {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        };
+        for verification in self.unlock(&mut manifest, encrypted, false)? {
+            if !verification.is_ok() {
+                return Err(Error::msg(verification.to_string()))
+                    .context("verifying integrity manifest");
+            }
+        }
+        Ok(Some(manifest))
+    }
+
+    /// The manifest to update on changes, created along with its keys for new databases.
+    ///
+    /// Writers without the keys cannot verify it, the manifest they get stays locked and is not
+    /// saved: their changes are left for someone with the keys to accept.
+    fn manifest(&self) -> Result<Manifest> {
+        let encrypted = self.b.list().context("listing secrets")?;
+        let mut manifest = self.load_manifest(&encrypted)?.unwrap_or_default();
+        if !self.b.supports_manifest() {
+            return Ok(manifest);
+        }
+        for verification in self.unlock(&mut manifest, &encrypted, true)? {
+            if !verification.is_ok() {
+                return Err(Error::msg(verification.to_string()))
+                    .context("verifying integrity manifest");
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Sign the sections of the manifest which changed and store it, along with any new key.
+    fn save_manifest(&mut self, mut manifest: Manifest) -> Result<()> {
+        if !self.b.supports_manifest() {
+            return Ok(());
+        }
+        let mut changed = false;
+        for prefix in self.unlocked_scopes() {
+            let name = key_name(&prefix);
+            let section = manifest.section_mut(&prefix);
+            if !section.unlocked() || !section.changed() {
+                continue;
+            }
+            if !section.entries().any(|(entry, _)| *entry == name) {
+                let key = section.key_entry(self.e.as_ref(), &name)?;
+                section.insert(&key, None);
+                self.b.store(key).context("storing manifest key")?;
+            }
+            section.sign().context("signing manifest")?;
+            changed = true;
+        }
+        if !changed {
+            return Ok(());
+        }
+        manifest.prune();
+        self.b.store_manifest(manifest).context("storing manifest")
+    }
+
+    /// The encrypted secrets of the database, as stored.
+    pub(crate) fn list_encrypted(&self) -> Result<Vec<Encrypted>> {
+        self.b.list().context("listing secrets")
+    }
+
+    /// Unlock the sections of `manifest` whose scope is unlocked, using the keys in `encrypted`.
+    pub(crate) fn unlock_manifest(
+        &self,
+        manifest: &mut Manifest,
+        encrypted: &[Encrypted],
+    ) -> Result<()> {
+        for prefix in self.unlocked_scopes() {
+            if let Some(key) = encrypted.iter().find(|e| e.name == key_name(&prefix)) {
+                manifest.section_mut(&prefix).unlock(self.e.as_ref(), key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The section of `manifest` covering the `name` secret, that of its key scope.
+    fn section<'m>(&self, manifest: &'m mut Manifest, name: &str) -> &'m mut Manifest {
+        manifest.section_mut(&self.route(name))
+    }
+
+    /// The prefix of the key scope `name` belongs to, empty for the default scope.
+    pub(crate) fn route(&self, name: &str) -> String {
+        self.e.scopes().into_iter().map(|scope| scope.prefix).filter(|prefix| {
+            !prefix.is_empty() && in_scope(name, prefix)
+        }).max_by_key(String::len).unwrap_or_default()
+    }
+
+    /// The prefixes of the key scopes which are unlocked, only the default one without scopes.
+    fn unlocked_scopes(&self) -> Vec<String> {
+        match self.e.scopes() {
+            scopes if scopes.is_empty() => vec![String::new()],
+            scopes => scopes.into_iter().filter(|s| s.unlocked).map(|s| s.prefix).collect(),
+        }
+    }
+}
+
+impl Secret {
+    /// Instantiate a new `Secret` without metadata.
+    pub fn new(name: String, secret: String) -> Self {
+        Self { name, secret, ..Default::default() }
+    }
+}
+
+impl Change {
+    /// The name of the added, removed or changed secret.
+    pub fn name(&self) -> &str {
+        match self {
+            Change::Added(secret) | Change::Removed(secret) | Change::Changed(secret, _) => {
+                &secret.name
+            }
+        }
+    }
 }
 
 impl Database {
@@ -73,45 +599,319 @@ impl Database {
     ///
     /// This call will read the provided `template` and replace all references to secrets with the
     /// secrets stored in the database. We use the [tinytemplate][1] engine, see their [syntax
-    /// page][2] for more context.
+    /// page][2] for more context, or [`template_with`][3] for other engines. On top of
+    /// `capitalize`, the `lower`, `shellquote`, `json`, `yaml`, `base64`, `base64decode`,
+    /// `urlencode`, `sha256` and `htpasswd` (bcrypt) formatters are available, as in
+    /// `{map.password | shellquote}`. Escape sequences such as `\n` are interpreted first, as for
+    /// templates passed on the command line.
+    ///
+    /// Templates see the secrets as a `list`, a `map` of names to secrets, a `meta` map of names
+    /// to their `description` and `tags`, and `groups`, which nests secrets by splitting their
+    /// name on `/` (`prod/db/password` is `groups.prod.db.password`.) Environment variables are
+    /// available in `env`, except those of hips (`HIPS_*`) and those which are not UTF-8.
+    ///
+    /// Only the secrets the template references as `map.<name>`, `meta.<name>` or `groups.<path>`
+    /// are decrypted, unless it uses `list`, which requires all of them.
     ///
     /// [1]: https://crates.io/crates/tinytemplate
     /// [2]: https://docs.rs/tinytemplate/1.0.4/tinytemplate/syntax/index.html
-    pub fn template(&self, mut template: String) -> Result<String> {
-        template = ::snailquote::unescape(&format!("\"{}\"", template))?;
+    /// [3]: #method.template_with
+    pub fn template(&self, template: String) -> Result<String> {
+        let template = ::snailquote::unescape(&format!("\"{}\"", template))?;
+        self.template_with(&crate::engines::TinyTemplate, template)
+    }
+
+    /// Process the database through a template, rendered by the provided [`Engine`][1].
+    ///
+    /// Unlike [`template`][2], escape sequences are left to the engine: the template is rendered
+    /// as written, as if read from a file.
+    ///
+    /// [1]: trait.Engine.html
+    /// [2]: #method.template
+    pub fn template_with(&self, engine: &dyn Engine, template: String) -> Result<String> {
+        let tctx = TemplateContext::new(self, &References::parse(&template))?;
+        engine.render(&template, &::serde_json::to_value(tctx)?)
+    }
+
+    /// Statically check the secrets referenced by a template against the database.
+    ///
+    /// Fails listing every `map.<name>` reference for which no secret exists. Otherwise, returns
+    /// the secrets starting with `prefix` that the template never references, if a prefix is
+    /// provided. A template iterating over `list` is considered to use every secret.
+    pub fn check_template(&self, template: String, prefix: Option<&str>) -> Result<Vec<String>> {
+        let references = References::parse(&template);
+        let names = self.names()?;
+
+        let mut missing = references.names.difference(&names).cloned().collect::<Vec<_>>();
+        missing.extend(references.groups.iter().filter(|group| {
+            !names.iter().any(|name| References::in_group(name, group))
+        }).cloned());
+        if !missing.is_empty() {
+            return Err(Error::msg(format!(
+                "template references missing secrets: {}", missing.join(", "),
+            )));
+        }
+
+        Ok(match prefix {
+            Some(prefix) if !references.all => names.into_iter().filter(|name| {
+                name.starts_with(prefix) && !references.contains(name)
+            }).collect(),
+            _ => vec![],
+        })
+    }
+
+    /// Render every template of the `src` directory into the `dest` directory.
+    ///
+    /// `*.tmpl` files are rendered using tinytemplate, `*.j2` files using [`Jinja`][1], which
+    /// resolves includes in `src`. Files whose name starts with `_` are only meant to be included
+    /// and are not rendered.
+    ///
+    /// Outputs keep their path relative to `src`, minus the suffix. They are written
+    /// atomically (through a temporary file renamed in place) with the provided unix `mode`, and
+    /// only if their content or mode changed. Returns the outputs that changed, or would have
+    /// changed if `check` is set, in which case nothing is written.
+    ///
+    /// [1]: engines/struct.Jinja.html
+    pub fn render(&self, src: &Path, dest: &Path, mode: u32, check: bool) -> Result<Vec<PathBuf>> {
+        let templates = templates(src).context("listing templates")?.into_iter().map(|path| {
+            let template = ::std::fs::read_to_string(src.join(&path))
+                .with_context(|| format!("reading {}", path.display()))?;
+            Ok((path, template))
+        }).collect::<Result<Vec<_>>>()?;
+
+        let mut references = References::default();
+        templates.iter().for_each(|(_, template)| references.extend(References::parse(template)));
+        let tctx = ::serde_json::to_value(TemplateContext::new(self, &references)?)?;
+        let jinja = crate::engines::Jinja::new(Some(src.to_owned()));
 
-        let mut tt = ::tinytemplate::TinyTemplate::new();
-        tt.add_template("template", &template)?;
-        tt.add_formatter("capitalize", |val, s| match val {
-            ::serde_json::Value::String(string) => {
-                s.push_str(&string.to_uppercase());
-                Ok(())
+        let mut changed = vec![];
+        for (template, content) in templates {
+            let output = template.with_extension("");
+            let engine: &dyn Engine = match template.extension() {
+                Some(ext) if ext == "j2" => &jinja,
+                _ => &crate::engines::TinyTemplate,
+            };
+            let rendered = engine.render(&content, &tctx)
+                .with_context(|| format!("rendering {}", template.display()))?;
+
+            let path = dest.join(&output);
+            if up_to_date(&path, &rendered, mode) {
+                continue;
+            }
+            if !check {
+                write_atomically(&path, &rendered, mode)
+                    .with_context(|| format!("writing {}", path.display()))?;
+            }
+            changed.push(output);
+        }
+        Ok(changed)
+    }
+}
+
+const OPAQUE_SCOPES: &str = "opaque names cannot be combined with key scopes";
+
+/// Separator between the segments of hierarchical names, as in `prod/db/password`.
+const SEPARATOR: &str = "/";
+
+/// Check a secret name: `.manifest` segments are reserved for the keys of the manifest.
+pub(crate) fn validate(name: &str) -> Result<()> {
+    match name.split(SEPARATOR).any(|segment| segment == KEY_NAME) {
+        true => Err(Error::msg(format!("invalid secret name: {}", name))),
+        false => validate_path(name),
+    }
+}
+
+/// Check a name as stored by backends, which may use it as a path: segments cannot be empty,
+/// `.` or `..`.
+pub(crate) fn validate_path(name: &str) -> Result<()> {
+    match name.split(SEPARATOR).any(|segment| ["", ".", ".."].contains(&segment)) {
+        true => Err(Error::msg(format!("invalid secret name: {}", name))),
+        false => Ok(()),
+    }
+}
+
+/// Whether `name` is `scope` or nested under it. Scopes may end with a separator.
+pub(crate) fn in_scope(name: &str, scope: &str) -> bool {
+    let scope = scope.trim_end_matches(SEPARATOR);
+    scope.is_empty()
+        || name == scope
+        || name.strip_prefix(scope).map(|rest| rest.starts_with(SEPARATOR)).unwrap_or(false)
+}
+
+/// Secrets referenced by a template, found by looking for `map.<name>`, `meta.<name>` and
+/// `groups.<path>`. Any other use of the secrets (iterating over `list`...) references them all.
+#[derive(Default)]
+struct References {
+    names: BTreeSet<String>,
+    groups: BTreeSet<String>,
+    all: bool,
+}
+
+impl References {
+    fn parse(template: &str) -> Self {
+        let mut references = Self::default();
+        for block in template.split('{').skip(1).filter_map(|rest| rest.split('}').next()) {
+            // Anything but a path is a separator, so aliases as in `{% set m=map %}` are found.
+            let words = block.split(|c: char| !(c.is_alphanumeric() || "_-.[".contains(c)));
+            for word in words.filter(|word| !word.is_empty()) {
+                let root = word.split('.').next().unwrap_or(word);
+                if ["include", "import", "extends"].contains(&word) || word.contains('[') {
+                    // Neither included templates nor subscripts are analysed.
+                    references.all = true;
+                } else if let (Some(name), "map" | "meta") = (word.split('.').nth(1), root) {
+                    references.names.insert(name.to_owned());
+                } else if let Some(path) = word.strip_prefix("groups.") {
+                    references.groups.insert(path.replace('.', SEPARATOR));
+                } else if ["list", "map", "meta", "groups"].contains(&root) {
+                    references.all = true;
+                }
             }
-            _ => panic!("can only capitalize strings"),
-        });
+        }
+        references
+    }
+
+    fn extend(&mut self, other: Self) {
+        self.names.extend(other.names);
+        self.groups.extend(other.groups);
+        self.all |= other.all;
+    }
 
-        let tctx = TemplateContext::try_from(self)?;
-        Ok(tt.render("template", &tctx)?)
+    /// Whether the `name` secret is referenced, directly or as part of a group.
+    fn contains(&self, name: &str) -> bool {
+        self.all
+            || self.names.contains(name)
+            || self.groups.iter().any(|group| Self::in_group(name, group))
+    }
+
+    fn in_group(name: &str, group: &str) -> bool {
+        !group.is_empty() && in_scope(name, group)
     }
 }
 
+/// Paths of the templates under `dir`, relative to it and sorted.
+fn templates(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = vec![];
+    for entry in ::std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        let relative = PathBuf::from(path.file_name().context("listing directory")?);
+        if path.is_dir() {
+            found.extend(templates(&path)?.into_iter().map(|p| relative.join(p)));
+        } else if relative.to_string_lossy().starts_with('_') {
+            continue;
+        } else if path.extension().map(|ext| ext == "tmpl" || ext == "j2").unwrap_or(false) {
+            found.push(relative);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Whether the output at `path` already has the rendered `content` and, on unix, the `mode`.
+fn up_to_date(path: &Path, content: &str, mode: u32) -> bool {
+    if ::std::fs::read_to_string(path).ok().as_deref() != Some(content) {
+        return false;
+    }
+    #[cfg(unix)]
+    return ::std::fs::metadata(path).map(|metadata| {
+        ::std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o7777 == mode
+    }).unwrap_or(false);
+    #[cfg(not(unix))]
+    {
+        let _ = mode;
+        true
+    }
+}
+
+fn write_atomically(path: &Path, content: &str, mode: u32) -> Result<()> {
+    let dir = path.parent().context("output has no parent directory")?;
+    ::std::fs::create_dir_all(dir).context("creating output directory")?;
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut options = ::std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    ::std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    let mut file = options.open(&tmp).context("opening temporary file")?;
+    #[cfg(unix)]
+    file.set_permissions(::std::os::unix::fs::PermissionsExt::from_mode(mode))
+        .context("setting permissions")?;
+    file.write_all(content.as_bytes()).context("writing temporary file")?;
+    file.sync_all().context("syncing temporary file")?;
+    ::std::fs::rename(&tmp, path).context("moving temporary file in place")
+}
+
 #[derive(Serialize)]
 struct TemplateContext {
     list: Vec<Secret>,
-    map: ::std::collections::HashMap<String, String>,
+    map: HashMap<String, String>,
+    meta: HashMap<String, Metadata>,
+    groups: ::serde_json::Map<String, ::serde_json::Value>,
+    env: HashMap<String, String>,
 }
 
-impl ::std::convert::TryFrom<&Database> for TemplateContext {
-    type Error = Error;
-    fn try_from(db: &Database) -> Result<Self> {
-        let secrets = db.list()?;
+#[derive(Serialize)]
+struct Metadata {
+    description: String,
+    tags: Vec<String>,
+}
+
+impl TemplateContext {
+    /// Decrypt only the secrets `references` points to.
+    fn new(db: &Database, references: &References) -> Result<Self> {
+        let secrets = if references.all {
+            db.select(None)?
+        } else if references.groups.is_empty() {
+            db.select(Some(&references.names))?
+        } else {
+            let names = db.names()?.into_iter().filter(|name| references.contains(name)).collect();
+            db.select(Some(&names))?
+        };
+
+        let mut groups = ::serde_json::Map::new();
+        for secret in &secrets {
+            let path = secret.name.split(SEPARATOR).collect::<Vec<_>>();
+            group(&mut groups, &path, &secret.secret);
+        }
+
         Ok(Self {
-            list: secrets.clone(),
-            map: ::std::collections::HashMap::from_iter(
+            list: if references.all { secrets.clone() } else { vec![] },
+            meta: HashMap::from_iter(secrets.iter().map(|secret| (secret.name.clone(), Metadata {
+                description: secret.description.clone(),
+                tags: secret.tags.clone(),
+            }))),
+            map: HashMap::from_iter(
                 secrets
                     .into_iter()
                     .map(|secret| (secret.name, secret.secret)),
             ),
+            groups,
+            // The environment of hips itself, holding its password, is not exposed.
+            env: ::std::env::vars_os().filter_map(|(key, value)| {
+                Some((key.into_string().ok()?, value.into_string().ok()?))
+            }).filter(|(key, _)| !key.starts_with("HIPS_")).collect(),
         })
     }
 }
+
+/// Insert `secret` in the nested `groups` at `path`. Groups win over secrets of the same name.
+fn group(groups: &mut ::serde_json::Map<String, ::serde_json::Value>, path: &[&str], secret: &str) {
+    match path {
+        [] => (),
+        [name] => {
+            groups.entry(*name).or_insert_with(|| secret.into());
+        }
+        [name, rest @ ..] => {
+            let entry = groups.entry(*name).or_insert_with(|| ::serde_json::Map::new().into());
+            if !entry.is_object() {
+                *entry = ::serde_json::Map::new().into();
+            }
+            if let ::serde_json::Value::Object(nested) = entry {
+                group(nested, rest, secret);
+            }
+        }
+    }
+}
@@ -3,6 +3,8 @@
 //! [1]: ../trait.Encrypter.html
 
 use crate::prelude::*;
+use ::base64::engine::general_purpose::STANDARD as BASE64;
+use ::base64::Engine;
 use ring::rand::SecureRandom;
 use std::convert::TryInto;
 
@@ -14,40 +16,52 @@ const ITERATIONS: usize = 100_000;
 
 /// Encrypt using the [ring][1] library.
 ///
-/// The key is generated from the password provided at initialization using the PBKDF2 scheme.
-/// The cipher we use is AES256 GCM with 100,000 iterations.
+/// The key is generated from the password provided at initialization using the PBKDF2 scheme with
+/// 100,000 iterations. The cipher is AES256 GCM by default, see [`Cipher`][2] for alternatives.
+/// The cipher is recorded alongside every secret, which means a database can be decrypted
+/// whatever cipher it was encrypted with.
 ///
 /// [1]: https://github.com/briansmith/ring
-pub struct Ring(String);
+/// [2]: enum.Cipher.html
+pub struct Ring {
+    password: String,
+    cipher: Cipher,
+}
 
 impl Ring {
     /// Instantiate a new `Encrypter` based on the ring library.
     pub fn new(password: String) -> Self {
-        Self(password)
+        Self::with_cipher(password, Cipher::Aes256Gcm)
+    }
+
+    /// Instantiate a new `Encrypter` based on the ring library, encrypting with `cipher`.
+    pub fn with_cipher(password: String, cipher: Cipher) -> Self {
+        Self { password, cipher }
     }
 
-    fn key(&self, salt: &[u8]) -> Result<Vec<u8>> {
+    fn key(&self, salt: &[u8], cipher: Cipher) -> Result<::ring::aead::LessSafeKey> {
         let mut key = [0; KEY_LEN];
         ::ring::pbkdf2::derive(
             ::ring::pbkdf2::PBKDF2_HMAC_SHA256,
             ::core::num::NonZeroU32::new(ITERATIONS as u32).expect("iterations not 0"),
-            &salt,
-            self.0.as_bytes(),
+            salt,
+            self.password.as_bytes(),
             &mut key,
         );
-        Ok(key.to_vec())
+        let key = ::ring::aead::UnboundKey::new(cipher.algorithm(), &key[..])
+            .map_err(|err| Error::msg(err.to_string()))
+            .context("generating unbound key")?;
+        Ok(::ring::aead::LessSafeKey::new(key))
     }
 }
 
 impl Encrypter for Ring {
     fn encrypt(&self, secret: Secret) -> Result<Encrypted> {
-        assert_eq!(::ring::aead::AES_256_GCM.tag_len(), TAG_SIZE);
+        assert_eq!(self.cipher.algorithm().tag_len(), TAG_SIZE);
 
         let plaintext = ::ring::aead::Aad::empty();
         let mut ciphertext = secret.secret.as_bytes().to_vec();
-        for _ in 0..::ring::aead::AES_256_GCM.tag_len() {
-            ciphertext.push(0);
-        }
+        ciphertext.extend(vec![0; self.cipher.algorithm().tag_len()]);
 
         let rand = ::ring::rand::SystemRandom::new();
         let mut salt = vec![0u8; SALT_SIZE];
@@ -58,55 +72,339 @@ impl Encrypter for Ring {
             .map_err(|err| Error::msg(format!("{}", err)))?;
         let nonce = ::ring::aead::Nonce::assume_unique_for_key(iv);
 
-        let key = self.key(&salt)?;
-        let key = ::ring::aead::UnboundKey::new(&::ring::aead::AES_256_GCM, &key[..])
-            .map_err(|err| Error::msg(err.to_string()))?;
-        let key = ::ring::aead::LessSafeKey::new(key);
-
+        let key = self.key(&salt, self.cipher)?;
         key.seal_in_place_append_tag(nonce, plaintext, &mut ciphertext)
             .map_err(|err| Error::msg(err.to_string()))?;
 
-        let ciphertext = ::base64::encode(
-            &iv.iter()
+        let ciphertext = BASE64.encode(
+            iv.iter()
                 .chain(ciphertext.iter())
-                .map(|v| *v)
+                .copied()
                 .collect::<Vec<u8>>(),
         );
-        let salt = ::base64::encode(&salt);
+        let salt = BASE64.encode(&salt);
 
         Ok(Encrypted {
             name: secret.name,
             secret: ciphertext,
             salt,
+            cipher: self.cipher.to_string(),
+            description: secret.description,
+            tags: secret.tags,
         })
     }
 
     fn decrypt(&self, encrypted: Encrypted) -> Result<Secret> {
+        let cipher = match encrypted.cipher.as_str() {
+            "" => Cipher::Aes256Gcm,
+            cipher => cipher.parse()?,
+        };
+
         let plaintext = ::ring::aead::Aad::empty();
-        let mut ciphertext = ::base64::decode(&encrypted.secret).context("decoding ciphertext")?;
+        let mut ciphertext = BASE64.decode(&encrypted.secret).context("decoding ciphertext")?;
+        if ciphertext.len() < IV_SIZE {
+            return Err(Error::msg("ciphertext is too short"));
+        }
 
         let iv = &ciphertext[..IV_SIZE];
         let nonce =
             ::ring::aead::Nonce::assume_unique_for_key(iv.try_into().context("transforming iv")?);
-        let mut ciphertext = &mut ciphertext[IV_SIZE..];
-        let salt = ::base64::decode(&encrypted.salt).context("decoding salt")?;
-
-        let key = self.key(&salt).context("computing key")?;
-        let key = ::ring::aead::UnboundKey::new(&::ring::aead::AES_256_GCM, &key[..])
-            .map_err(|err| Error::msg(err.to_string()))
-            .context("generating unbound key")?;
-        let key = ::ring::aead::LessSafeKey::new(key);
+        let ciphertext = &mut ciphertext[IV_SIZE..];
+        let salt = BASE64.decode(&encrypted.salt).context("decoding salt")?;
 
+        let key = self.key(&salt, cipher).context("computing key")?;
         let secret = key
-            .open_in_place(nonce, plaintext, &mut ciphertext)
+            .open_in_place(nonce, plaintext, ciphertext)
             .map_err(|err| Error::msg(err.to_string()))
-            .context("running aes_256_gcm")?;
+            .with_context(|| format!("running {}", cipher))?;
 
         Ok(Secret {
             name: encrypted.name,
             secret: ::std::str::from_utf8(&secret[..(secret.len() - TAG_SIZE)])
                 .context("loading as utf8")?
                 .to_owned(),
+            description: encrypted.description,
+            tags: encrypted.tags,
+        })
+    }
+}
+
+/// Ciphers available to the [`Ring`][1] encrypter.
+///
+/// AES256 GCM is the fastest on hardware supporting AES instructions. ChaCha20 Poly1305 is faster
+/// and constant-time on hardware that does not (some ARM boxes for example.)
+///
+/// [1]: struct.Ring.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn algorithm(self) -> &'static ::ring::aead::Algorithm {
+        match self {
+            Self::Aes256Gcm => &::ring::aead::AES_256_GCM,
+            Self::ChaCha20Poly1305 => &::ring::aead::CHACHA20_POLY1305,
+        }
+    }
+}
+
+impl ::std::str::FromStr for Cipher {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "aes-256-gcm" => Ok(Self::Aes256Gcm),
+            "chacha20-poly1305" => Ok(Self::ChaCha20Poly1305),
+            _ => Err(Error::msg(format!("unsupported cipher: {}", s))),
+        }
+    }
+}
+
+impl ::std::fmt::Display for Cipher {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.write_str(match self {
+            Self::Aes256Gcm => "aes-256-gcm",
+            Self::ChaCha20Poly1305 => "chacha20-poly1305",
+        })
+    }
+}
+
+/// Encrypt using the [age][1] file format.
+///
+/// Every secret is stored as an ASCII-armored age file, which means it can be decrypted using the
+/// standard age tooling (`age -d`) if hips is not available. Three modes are supported: passphrase
+/// (scrypt), a list of X25519 recipients or a list of SSH public keys, along with the identities
+/// used for decryption.
+///
+/// [1]: https://age-encryption.org/v1
+pub struct Age {
+    recipients: Vec<Box<dyn ::age::Recipient + Send>>,
+    identities: Vec<Box<dyn ::age::Identity + Send + Sync>>,
+}
+
+impl Age {
+    /// Instantiate a new `Encrypter` using an age passphrase (scrypt.)
+    pub fn with_passphrase(passphrase: String) -> Self {
+        Self {
+            recipients: vec![Box::new(::age::scrypt::Recipient::new(passphrase.clone().into()))],
+            identities: vec![Box::new(::age::scrypt::Identity::new(passphrase.into()))],
+        }
+    }
+
+    /// Instantiate a new `Encrypter` using X25519 recipients and identities.
+    ///
+    /// Both arguments use the formats of the age tooling: one `age1...` recipient per line for
+    /// `recipients`, the output of `age-keygen` for `identities`. Empty lines and `#` comments are
+    /// ignored. `identities` may be empty if the database is only written to.
+    pub fn with_recipients(recipients: &str, identities: &str) -> Result<Self> {
+        Ok(Self {
+            recipients: recipients.lines().map(str::trim).filter(|line| {
+                !line.is_empty() && !line.starts_with('#')
+            }).map(|line| {
+                line.parse::<::age::x25519::Recipient>().map(|r| {
+                    Box::new(r) as Box<dyn ::age::Recipient + Send>
+                }).map_err(|err| Error::msg(format!("{}: {}", err, line)))
+            }).collect::<Result<_>>().context("parsing recipients")?,
+            identities: ::age::IdentityFile::from_buffer(identities.as_bytes())
+                .context("reading identities")?
+                .into_identities()
+                .context("parsing identities")?,
+        })
+    }
+
+    /// Instantiate a new `Encrypter` using SSH public keys as recipients.
+    ///
+    /// `recipients` follows the `authorized_keys` format: one `ssh-ed25519` (or `ssh-rsa`) public
+    /// key per line, optionally preceded by options and followed by a comment. `identity` is the
+    /// content of an OpenSSH private key file, which is unlocked using `passphrase` if it is
+    /// protected. `identity` may be empty if the database is only written to.
+    pub fn with_ssh_keys(
+        recipients: &str,
+        identity: &str,
+        passphrase: Option<String>,
+    ) -> Result<Self> {
+        let identities: Vec<Box<dyn ::age::Identity + Send + Sync>> = if identity.is_empty() {
+            vec![]
+        } else {
+            let key = match ::age::ssh::Identity::from_buffer(identity.as_bytes(), None)
+                .context("reading private key")?
+            {
+                ::age::ssh::Identity::Unencrypted(key) => key,
+                ::age::ssh::Identity::Encrypted(key) => key
+                    .decrypt(passphrase.context("private key is passphrase-protected")?.into())
+                    .context("unlocking private key")?,
+                ::age::ssh::Identity::Unsupported(_) => {
+                    return Err(Error::msg("unsupported private key type"))
+                }
+            };
+            vec![Box::new(::age::ssh::Identity::from(key))]
+        };
+
+        Ok(Self {
+            recipients: recipients.lines().map(str::trim).filter(|line| {
+                !line.is_empty() && !line.starts_with('#')
+            }).map(|line| {
+                ssh_recipient(line).with_context(|| format!("parsing {}", line))
+            }).collect::<Result<_>>().context("parsing recipients")?,
+            identities,
         })
     }
 }
+
+/// Parse an `authorized_keys` line, `[options] keytype base64-key [comment]`, as in sshd(8).
+///
+/// Options are separated by commas and may contain whitespace within double quotes (as in
+/// `command="echo hi"`), where `\"` escapes a quote. age can only encrypt to `ssh-ed25519` and
+/// `ssh-rsa` keys, other key types (`ecdsa-sha2-*`, `sk-*`...) are reported as unsupported.
+fn ssh_recipient(line: &str) -> Result<Box<dyn ::age::Recipient + Send>> {
+    let is_key_type = |field: &str| {
+        ["ssh-", "ecdsa-sha2-", "sk-"].iter().any(|prefix| field.starts_with(prefix))
+    };
+    let key = match line.split_whitespace().next() {
+        Some(field) if is_key_type(field) => line,
+        _ => {
+            let (mut quoted, mut escaped) = (false, false);
+            let end = line.find(|c: char| {
+                let end = !quoted && c.is_whitespace();
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' if quoted => escaped = true,
+                    '"' => quoted = !quoted,
+                    _ => (),
+                }
+                end
+            });
+            match end {
+                Some(end) if !quoted => &line[end..],
+                _ => return Err(Error::msg("no key after the options")),
+            }
+        }
+    };
+
+    let key = key.split_whitespace().take(2).collect::<Vec<_>>().join(" ");
+    match key.parse::<::age::ssh::Recipient>() {
+        Ok(recipient) => Ok(Box::new(recipient)),
+        Err(::age::ssh::ParseRecipientKeyError::Unsupported(key_type)) => Err(Error::msg(
+            format!("unsupported key type {}, use ssh-ed25519 or ssh-rsa", key_type),
+        )),
+        Err(err) => Err(Error::msg(format!("{:?}", err))),
+    }
+}
+
+impl Encrypter for Age {
+    fn encrypt(&self, secret: Secret) -> Result<Encrypted> {
+        let encryptor = ::age::Encryptor::with_recipients(
+            self.recipients.iter().map(|r| r.as_ref() as &dyn ::age::Recipient),
+        ).context("preparing recipients")?;
+
+        let mut ciphertext = vec![];
+        let armor = ::age::armor::ArmoredWriter::wrap_output(
+            &mut ciphertext,
+            ::age::armor::Format::AsciiArmor,
+        )?;
+        let mut writer = encryptor.wrap_output(armor)?;
+        writer.write_all(secret.secret.as_bytes()).context("encrypting secret")?;
+        writer.finish()?.finish().context("armoring ciphertext")?;
+
+        Ok(Encrypted {
+            name: secret.name,
+            secret: String::from_utf8(ciphertext).context("loading armor as utf8")?,
+            salt: String::new(),
+            cipher: String::new(),
+            description: secret.description,
+            tags: secret.tags,
+        })
+    }
+
+    fn decrypt(&self, encrypted: Encrypted) -> Result<Secret> {
+        let decryptor = ::age::Decryptor::new_buffered(
+            ::age::armor::ArmoredReader::new(encrypted.secret.as_bytes()),
+        ).context("reading age header")?;
+
+        let mut secret = String::new();
+        decryptor
+            .decrypt(self.identities.iter().map(|i| i.as_ref() as &dyn ::age::Identity))
+            .context("unwrapping file key")?
+            .read_to_string(&mut secret)
+            .context("decrypting payload")?;
+
+        Ok(Secret {
+            name: encrypted.name,
+            secret,
+            description: encrypted.description,
+            tags: encrypted.tags,
+        })
+    }
+
+    fn write_only(&self) -> bool {
+        self.identities.is_empty()
+    }
+}
+
+/// Encrypt parts of the database under different keys, using one `Encrypter` per scope.
+///
+/// Secrets are routed to the scope with the longest prefix matching their name (see
+/// [`Database::list_scope`][1]), or to the default scope. The encrypter of a scope is optional:
+/// without it, the scope is locked and its secrets can neither be encrypted nor decrypted. This
+/// way, someone with the `staging/` key only can work on `staging/*` secrets.
+///
+/// The integrity manifest has a section per scope, each with its own key stored in the scope:
+/// someone with the `staging/` key can only verify and sign the `staging/*` secrets. Opaque
+/// names cannot be routed, databases using them cannot be written to with scopes.
+///
+/// [1]: ../struct.Database.html#method.list_scope
+pub struct Scoped {
+    default: Option<Box<dyn Encrypter>>,
+    scopes: Vec<(String, Option<Box<dyn Encrypter>>)>,
+}
+
+impl Scoped {
+    /// Instantiate a new `Encrypter`, using `default` for secrets outside of any scope.
+    pub fn new(default: Option<Box<dyn Encrypter>>) -> Self {
+        Self { default, scopes: vec![] }
+    }
+
+    /// Add a scope covering the secrets under `prefix`, locked if there is no `encrypter`.
+    pub fn with_scope(mut self, prefix: String, encrypter: Option<Box<dyn Encrypter>>) -> Self {
+        self.scopes.push((prefix, encrypter));
+        self
+    }
+
+    fn route(&self, name: &str) -> (&str, Option<&dyn Encrypter>) {
+        self.scopes
+            .iter()
+            .filter(|(prefix, _)| crate::database::in_scope(name, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, e)| (prefix.as_str(), e.as_deref()))
+            .unwrap_or(("", self.default.as_deref()))
+    }
+
+    fn all(&self) -> impl Iterator<Item = (&str, Option<&dyn Encrypter>)> {
+        ::std::iter::once(("", self.default.as_deref()))
+            .chain(self.scopes.iter().map(|(prefix, e)| (prefix.as_str(), e.as_deref())))
+    }
+}
+
+impl Encrypter for Scoped {
+    fn encrypt(&self, secret: Secret) -> Result<Encrypted> {
+        match self.route(&secret.name) {
+            (_, Some(e)) => e.encrypt(secret),
+            (prefix, None) => Err(Error::msg(format!("scope '{}' is locked", prefix))),
+        }
+    }
+
+    fn decrypt(&self, encrypted: Encrypted) -> Result<Secret> {
+        match self.route(&encrypted.name) {
+            (_, Some(e)) => e.decrypt(encrypted),
+            (prefix, None) => Err(Error::msg(format!("scope '{}' is locked", prefix))),
+        }
+    }
+
+    fn scopes(&self) -> Vec<Scope> {
+        self.all().map(|(prefix, e)| Scope {
+            prefix: prefix.to_owned(),
+            unlocked: e.is_some(),
+        }).collect()
+    }
+}
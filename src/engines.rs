@@ -0,0 +1,74 @@
+//! [`Engine`][1] trait implementations.
+//!
+//! [1]: ../trait.Engine.html
+
+use crate::formatters::FORMATTERS;
+use crate::prelude::*;
+
+/// Render using the [tinytemplate][1] library, the default.
+///
+/// Formatters are applied as in `{map.password | shellquote}`. See the [syntax page][2].
+///
+/// [1]: https://crates.io/crates/tinytemplate
+/// [2]: https://docs.rs/tinytemplate/1.0.4/tinytemplate/syntax/index.html
+pub struct TinyTemplate;
+
+impl Engine for TinyTemplate {
+    fn render(&self, template: &str, context: &::serde_json::Value) -> Result<String> {
+        let mut tt = ::tinytemplate::TinyTemplate::new();
+        tt.add_template("template", template)?;
+        for (name, formatter) in FORMATTERS {
+            tt.add_formatter(name, move |val, s| match val {
+                ::serde_json::Value::String(string) => {
+                    s.push_str(&formatter(string).map_err(|err| {
+                        ::tinytemplate::error::Error::GenericError {
+                            msg: format!("{}: {:#}", name, err),
+                        }
+                    })?);
+                    Ok(())
+                }
+                _ => Err(::tinytemplate::error::Error::GenericError {
+                    msg: format!("{} can only format strings, got {}", name, val),
+                }),
+            });
+        }
+        Ok(tt.render("template", context)?)
+    }
+}
+
+/// Render using the [minijinja][1] library, a Jinja2 implementation.
+///
+/// On top of Jinja's own filters, formatters are available as filters, as in
+/// `{{ map.password | shellquote }}`. Includes (`{% include "_partial.j2" %}`) are resolved
+/// relatively to the `includes` directory, if provided. Undefined values fail the rendering.
+///
+/// [1]: https://crates.io/crates/minijinja
+pub struct Jinja {
+    includes: Option<PathBuf>,
+}
+
+impl Jinja {
+    /// Instantiate a new Jinja `Engine`, resolving includes in the `includes` directory.
+    pub fn new(includes: Option<PathBuf>) -> Self {
+        Self { includes }
+    }
+}
+
+impl Engine for Jinja {
+    fn render(&self, template: &str, context: &::serde_json::Value) -> Result<String> {
+        let mut env = ::minijinja::Environment::new();
+        env.set_undefined_behavior(::minijinja::UndefinedBehavior::Strict);
+        if let Some(includes) = &self.includes {
+            env.set_loader(::minijinja::path_loader(includes));
+        }
+        for (name, formatter) in FORMATTERS {
+            env.add_filter(*name, move |val: String| {
+                formatter(&val).map_err(|err| ::minijinja::Error::new(
+                    ::minijinja::ErrorKind::InvalidOperation,
+                    format!("{}: {:#}", name, err),
+                ))
+            });
+        }
+        env.render_str(template, context).map_err(|err| Error::msg(format!("{:#}", err)))
+    }
+}
@@ -0,0 +1,169 @@
+//! Export of plaintext secrets to other tools' files.
+//!
+//! The opposite of [`importers`][1]: secrets listed from the database, usually through
+//! [`Database::list_scope`][2], are written in one of the supported [`Format`][3]s.
+//!
+//! [1]: ../importers/index.html
+//! [2]: ../struct.Database.html#method.list_scope
+//! [3]: enum.Format.html
+
+use crate::prelude::*;
+use ::base64::engine::general_purpose::STANDARD as BASE64;
+use ::base64::Engine as _;
+use ::std::collections::BTreeMap;
+
+/// The format of an export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `NAME="value"` lines, double-quoted and escaped so that dotenv libraries read them back.
+    Dotenv,
+    /// `NAME=value` lines for docker's `--env-file`, which takes values literally.
+    DockerEnv,
+    /// A JSON object, hierarchical names giving nested objects.
+    Json,
+    /// A YAML mapping, hierarchical names giving nested mappings.
+    Yaml,
+    /// A Kubernetes `Secret` manifest, with the base64-encoded values under `data`.
+    K8sSecret,
+}
+
+impl ::std::str::FromStr for Format {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dotenv" => Ok(Self::Dotenv),
+            "docker-env" => Ok(Self::DockerEnv),
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            "k8s-secret" => Ok(Self::K8sSecret),
+            _ => Err(Error::msg(format!("unsupported format: {}", s))),
+        }
+    }
+}
+
+/// The environment variable name of a secret: `db/password` becomes `DB_PASSWORD`.
+pub fn env_var_name(name: &str) -> String {
+    name.chars().map(|c| match c {
+        c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+        _ => '_',
+    }).collect()
+}
+
+/// Write `secrets` in `format`.
+///
+/// Environment formats name variables after [`env_var_name`][1]. The Kubernetes `Secret` is
+/// named `name` (mandatory for that format), in `namespace` if any, its keys being the secret
+/// names with `/` (and any other character Kubernetes rejects) replaced by `_`.
+///
+/// [1]: fn.env_var_name.html
+pub fn export(
+    secrets: &[Secret],
+    format: Format,
+    name: Option<&str>,
+    namespace: Option<&str>,
+) -> Result<String> {
+    match format {
+        Format::Dotenv => env(secrets, |value| Ok(quote(value))),
+        Format::DockerEnv => env(secrets, |value| match value.contains(&['\n', '\r'][..]) {
+            true => Err(Error::msg("docker env files cannot hold multi-line values")),
+            false => Ok(value.to_owned()),
+        }),
+        Format::Json => Ok(::serde_json::to_string_pretty(&nest(secrets)?)? + "\n"),
+        Format::Yaml => Ok(::serde_yaml::to_string(&nest(secrets)?)?),
+        Format::K8sSecret => {
+            let mut data = BTreeMap::new();
+            for secret in secrets {
+                let key = secret.name.chars().map(|c| match c {
+                    c if c.is_ascii_alphanumeric() || c == '-' || c == '.' => c,
+                    _ => '_',
+                }).collect::<String>();
+                if data.insert(key.clone(), BASE64.encode(&secret.secret)).is_some() {
+                    return Err(Error::msg(format!("several secrets are exported as {}", key)));
+                }
+            }
+            Ok(::serde_yaml::to_string(&K8sSecret {
+                api_version: "v1",
+                kind: "Secret",
+                metadata: K8sMetadata {
+                    name: name.context("kubernetes secrets need a name")?,
+                    namespace,
+                },
+                r#type: "Opaque",
+                data,
+            })?)
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct K8sSecret<'a> {
+    api_version: &'a str,
+    kind: &'a str,
+    metadata: K8sMetadata<'a>,
+    r#type: &'a str,
+    data: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct K8sMetadata<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<&'a str>,
+}
+
+fn env(secrets: &[Secret], value: impl Fn(&str) -> Result<String>) -> Result<String> {
+    let mut lines = BTreeMap::new();
+    for secret in secrets {
+        let name = env_var_name(&secret.name);
+        let line = format!("{}={}\n", name, value(&secret.secret).context(secret.name.clone())?);
+        if lines.insert(name.clone(), line).is_some() {
+            return Err(Error::msg(format!("several secrets are exported as {}", name)));
+        }
+    }
+    Ok(lines.into_values().collect())
+}
+
+/// Double-quote a dotenv value, escaping what would be interpreted inside the quotes.
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '"' | '\\' | '$' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Turn hierarchical names into nested objects, `db/password` giving `{"db": {"password": ..}}`.
+fn nest(secrets: &[Secret]) -> Result<::serde_json::Value> {
+    use ::serde_json::{Map, Value};
+    let mut root = Map::new();
+    for secret in secrets {
+        let mut object = &mut root;
+        let mut segments = secret.name.split('/').peekable();
+        while let Some(segment) = segments.next() {
+            let conflict = || Error::msg(format!("{} is both a secret and a group", secret.name));
+            if segments.peek().is_none() {
+                if object.contains_key(segment) {
+                    return Err(conflict());
+                }
+                object.insert(segment.to_owned(), Value::String(secret.secret.clone()));
+            } else {
+                object = match object.entry(segment).or_insert_with(|| Map::new().into()) {
+                    Value::Object(object) => object,
+                    _ => return Err(conflict()),
+                };
+            }
+        }
+    }
+    Ok(Value::Object(root))
+}
@@ -0,0 +1,80 @@
+//! Formatters available to templates, as in `{map.password | shellquote}`.
+//!
+//! They are independent of the template engine, each [`Engine`][1] adapts them.
+//!
+//! [1]: ../trait.Engine.html
+
+use crate::prelude::*;
+use ::base64::engine::general_purpose::STANDARD as BASE64;
+use ::base64::Engine;
+
+/// A formatter transforms the string it is provided.
+pub type Formatter = fn(&str) -> Result<String>;
+
+/// All formatters and their names.
+pub const FORMATTERS: &[(&str, Formatter)] = &[
+    ("capitalize", capitalize),
+    ("lower", lower),
+    ("shellquote", shellquote),
+    ("json", json),
+    ("yaml", yaml),
+    ("base64", base64),
+    ("base64decode", base64decode),
+    ("urlencode", urlencode),
+    ("sha256", sha256),
+    ("htpasswd", htpasswd),
+];
+
+fn capitalize(val: &str) -> Result<String> {
+    Ok(val.to_uppercase())
+}
+
+fn lower(val: &str) -> Result<String> {
+    Ok(val.to_lowercase())
+}
+
+/// Single-quote the string for POSIX shells, which leaves nothing to interpret but `'` itself.
+fn shellquote(val: &str) -> Result<String> {
+    Ok(format!("'{}'", val.replace('\'', "'\\''")))
+}
+
+/// Encode as a JSON string, surrounding quotes included.
+fn json(val: &str) -> Result<String> {
+    Ok(::serde_json::to_string(val)?)
+}
+
+/// Encode as a YAML double-quoted scalar, which escapes like a JSON string.
+fn yaml(val: &str) -> Result<String> {
+    json(val)
+}
+
+fn base64(val: &str) -> Result<String> {
+    Ok(BASE64.encode(val))
+}
+
+fn base64decode(val: &str) -> Result<String> {
+    let decoded = BASE64.decode(val).context("decoding base64")?;
+    String::from_utf8(decoded).context("loading decoded base64 as utf8")
+}
+
+/// Percent-encode everything but the URL unreserved characters.
+fn urlencode(val: &str) -> Result<String> {
+    Ok(val.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+            (byte as char).to_string()
+        }
+        _ => format!("%{:02X}", byte),
+    }).collect())
+}
+
+/// Hex-encoded SHA256 digest of the string.
+fn sha256(val: &str) -> Result<String> {
+    let digest = ::ring::digest::digest(&::ring::digest::SHA256, val.as_bytes());
+    Ok(digest.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Bcrypt hash in the `$2y$` flavor understood by apache's `htpasswd` files.
+fn htpasswd(val: &str) -> Result<String> {
+    let hash = ::bcrypt::hash_with_result(val, ::bcrypt::DEFAULT_COST).context("hashing")?;
+    Ok(hash.format_for_version(::bcrypt::Version::TwoY))
+}
@@ -0,0 +1,254 @@
+//! Git integration for databases stored in a repository.
+
+use crate::prelude::*;
+
+/// The result of a three-way [`merge`][1] of YAML databases.
+///
+/// [1]: fn.merge.html
+pub struct Merge {
+    /// The merged database, in YAML.
+    pub content: String,
+    /// Names of the secrets changed differently on both sides, left as in `ours`.
+    pub conflicts: Vec<String>,
+}
+
+/// Merge two versions of a [`YAML`][1] database, `ours` and `theirs`, given their common `base`.
+///
+/// Secrets are merged by name: a secret changed (or added, or removed) on one side only takes
+/// that side's version. Secrets changed on both sides are conflicts, unless both made the same
+/// change. Since every encryption yields a different ciphertext, storing the same value on both
+/// sides is a conflict too. `ours`' order is kept, `theirs`' additions are appended.
+///
+/// [1]: ../backends/struct.YAML.html
+pub fn merge(base: &str, ours: &str, theirs: &str) -> Result<Merge> {
+    let base = parse(base).context("parsing base")?;
+    let ours = parse(ours).context("parsing ours")?;
+    let theirs = parse(theirs).context("parsing theirs")?;
+    let find = |secrets: &[Encrypted], name: &str| secrets.iter().find(|s| s.name == name).cloned();
+
+    let mut names = ours.iter().map(|s| s.name.clone()).collect::<Vec<_>>();
+    names.extend(theirs.iter().map(|s| s.name.clone()).filter(|name| find(&ours, name).is_none()));
+
+    let (mut merged, mut conflicts) = (vec![], vec![]);
+    for name in names {
+        let (o, a, b) = (find(&base, &name), find(&ours, &name), find(&theirs, &name));
+        let resolved = if a == b || b == o {
+            a
+        } else if a == o {
+            b
+        } else {
+            conflicts.push(name);
+            a
+        };
+        merged.extend(resolved);
+    }
+
+    Ok(Merge {
+        content: ::serde_yaml::to_string(&merged).context("marshalling to yaml")?,
+        conflicts,
+    })
+}
+
+/// Merge two versions of a database's integrity [`Manifest`][1], `ours` and `theirs`, given
+/// their common `base`.
+///
+/// The entries of the manifest are merged the same way [`merge`][2] merges secrets, so that the
+/// result covers the merged database. The sections whose key is in `db` (the database the
+/// manifest belongs to) are checked on both sides, then signed again. Other sections can only be
+/// taken from one side, changes to them on both sides are conflicts.
+///
+/// [1]: ../struct.Manifest.html
+/// [2]: fn.merge.html
+pub fn merge_manifest(db: &Database, base: &str, ours: &str, theirs: &str) -> Result<Merge> {
+    let parse = |content: &str| -> Result<Manifest> {
+        match content.trim().is_empty() {
+            true => Ok(Manifest::default()),
+            false => Ok(::serde_yaml::from_str(content)?),
+        }
+    };
+    let base = parse(base).context("parsing base")?;
+    let mut merged = parse(ours).context("parsing ours")?;
+    let mut theirs = parse(theirs).context("parsing theirs")?;
+    let keys = db.list_encrypted()?;
+    db.unlock_manifest(&mut merged, &keys).context("unlocking ours")?;
+    db.unlock_manifest(&mut theirs, &keys).context("unlocking theirs")?;
+
+    let prefixes = merged.sections().chain(theirs.sections()).map(|(prefix, _)| {
+        prefix.to_owned()
+    }).collect::<::std::collections::BTreeSet<_>>();
+    let mut conflicts = vec![];
+    for prefix in prefixes {
+        let empty = Manifest::default();
+        let o = base.section(&prefix).unwrap_or(&empty);
+        let b = theirs.section(&prefix).unwrap_or(&empty);
+        let a = merged.section_mut(&prefix);
+        if a.unlocked() {
+            conflicts.extend(a.merge(o, b).with_context(|| format!("merging scope '{}'", prefix))?);
+        } else if !a.take(o, b) {
+            conflicts.push(format!("scope '{}'", prefix));
+        }
+    }
+    merged.prune();
+
+    Ok(Merge {
+        content: ::serde_yaml::to_string(&merged).context("marshalling to yaml")?,
+        conflicts,
+    })
+}
+
+fn parse(content: &str) -> Result<Vec<Encrypted>> {
+    match content.trim().is_empty() {
+        true => Ok(vec![]),
+        false => Ok(::serde_yaml::from_str(content)?),
+    }
+}
+
+/// A stable, sorted view of a database or manifest file, meant as a git `textconv` filter.
+///
+/// For a [`YAML`][1] database, every secret is listed with a fingerprint, or with its value if
+/// `plaintext` is set. Secrets which cannot be decrypted are listed as such. For a manifest, the
+/// only file of a [`Folder`][2] database worth diffing, secrets are listed with the fingerprint
+/// it records and nothing is decrypted. Either way, `git diff` shows which secrets were added,
+/// removed or changed.
+///
+/// Fingerprints are keyed using the integrity manifest's keys, which `db` (the database the file
+/// belongs to, in any version) provides. They only change along with the secrets, not every time
+/// they are encrypted again. Opaque names are resolved too, in manifests only for the secrets
+/// still in `db`.
+///
+/// [1]: ../backends/struct.YAML.html
+/// [2]: ../backends/struct.Folder.html
+pub fn textconv(db: &Database, content: &str, plaintext: bool) -> Result<String> {
+    let value: ::serde_yaml::Value = ::serde_yaml::from_str(content).context("parsing yaml")?;
+    // The keys never change, those of the current database work for its older versions too.
+    let mut keys = Manifest::default();
+    if let Ok(encrypted) = db.list_encrypted() {
+        db.unlock_manifest(&mut keys, &encrypted).ok();
+    }
+
+    let (header, mut lines) = if value.is_mapping() {
+        let manifest: Manifest = ::serde_yaml::from_value(value).context("parsing manifest")?;
+        let names = match keys.opaque() {
+            true => db.list_names("").unwrap_or_default().into_iter().filter_map(|name| {
+                Some((keys.stored_name(&name).ok()?, name))
+            }).collect(),
+            false => ::std::collections::HashMap::new(),
+        };
+        let revisions = manifest.sections().map(|(prefix, section)| match prefix {
+            "" => format!("revision: {}", section.revision()),
+            prefix => format!("revision {}: {}", prefix, section.revision()),
+        });
+        let lines = manifest.sections().flat_map(|(_, section)| {
+            section.entries().filter(|(name, _)| !crate::manifest::is_key(name)).map(move |e| {
+                let (name, digest) = e;
+                (name, section.recorded_fingerprint(name).unwrap_or(digest))
+            })
+        }).map(|(name, fingerprint)| {
+            format!("{}: {}", names.get(name).unwrap_or(name), fingerprint)
+        });
+        (revisions.collect(), lines.collect())
+    } else {
+        let encrypted = parse(content)?;
+        // Versions with their own keys also carry their opaque mode.
+        db.unlock_manifest(&mut keys, &encrypted).ok();
+        let secrets = encrypted.into_iter().filter(|e| !crate::manifest::is_key(&e.name));
+        (vec![], secrets.map(|encrypted| {
+            let name = encrypted.name.clone();
+            let section = keys.section(&db.route(&name)).filter(|section| section.unlocked());
+            Ok(match db.unseal(Some(&keys), encrypted) {
+                Ok(secret) if plaintext => {
+                    format!("{}: {}", secret.name, ::serde_json::to_string(&secret.secret)?)
+                }
+                Ok(secret) => match section {
+                    Some(section) => {
+                        format!("{}: {}", secret.name, section.fingerprint(&secret)?)
+                    }
+                    None => format!("{}: <no fingerprint key>", secret.name),
+                },
+                Err(_) => format!("{}: <cannot decrypt>", name),
+            })
+        }).collect::<Result<Vec<_>>>()?)
+    };
+    lines.sort();
+    Ok(header.into_iter().chain(lines).map(|line| line + "\n").collect())
+}
+
+/// The default naming [`scheme`][1] of git credentials.
+///
+/// [1]: fn.credential.html
+pub const CREDENTIAL_SCHEME: &str = "git/{protocol}/{host}";
+
+/// Answer a git [credential helper][1] request: `action` is `get`, `store` or `erase`, `input`
+/// the credential's `key=value` lines.
+///
+/// Credentials are named after `scheme`, where `{protocol}`, `{host}`, `{path}` and `{username}`
+/// are replaced by the credential's attributes, empty segments being dropped: `git/{host}`
+/// stores github.com's credential as `git/github.com/password`, and its username as
+/// `git/github.com/username`. The protocol should be part of the scheme, so that credentials
+/// stored for https are not sent over plain http (see [`CREDENTIAL_SCHEME`][2].)
+///
+/// `get` answers nothing when there is no such credential, for git to try its other helpers.
+/// `get` and `erase` ignore credentials stored for another username than the requested one.
+/// Other actions are ignored, as the protocol requires.
+///
+/// [1]: https://git-scm.com/docs/gitcredentials#_custom_helpers
+/// [2]: constant.CREDENTIAL_SCHEME.html
+pub fn credential(db: &mut Database, action: &str, input: &str, scheme: &str) -> Result<String> {
+    let attributes = input.lines().take_while(|line| !line.is_empty()).filter_map(|line| {
+        line.split_once('=')
+    }).collect::<::std::collections::BTreeMap<_, _>>();
+    let attribute = |key: &str| attributes.get(key).copied().unwrap_or_default();
+    let name = ["protocol", "host", "path", "username"].iter().fold(
+        scheme.to_owned(), |name, key| name.replace(&format!("{{{}}}", key), attribute(key)),
+    ).split('/').filter(|segment| !segment.is_empty()).collect::<Vec<_>>().join("/");
+    if name.is_empty() {
+        return Err(Error::msg(format!("no credential name from scheme {}", scheme)));
+    }
+    let (username, password) = (format!("{}/username", name), format!("{}/password", name));
+    let existing = db.list_names(&name)?;
+    if action == "get" && !existing.contains(&password) {
+        return Ok(String::new());
+    }
+    let stored = match existing.contains(&username) && (action == "get" || action == "erase") {
+        true => Some(db.load(username.clone())?.secret),
+        false => None,
+    };
+    let matches = attribute("username").is_empty()
+        || stored.as_deref() == Some(attribute("username"));
+
+    match action {
+        "get" if matches => {
+            let mut output = String::new();
+            if let Some(stored) = stored {
+                output.push_str(&line("username", &stored)?);
+            }
+            output.push_str(&line("password", &db.load(password)?.secret)?);
+            Ok(output)
+        }
+        "store" if !attribute("password").is_empty() => {
+            let mut store = |name: String, secret: &str| {
+                db.store(Secret { name, secret: secret.to_owned(), ..Default::default() })
+            };
+            if !attribute("username").is_empty() {
+                store(username, attribute("username"))?;
+            }
+            store(password, attribute("password"))?;
+            Ok(String::new())
+        }
+        "erase" if matches => {
+            for name in existing.into_iter().filter(|n| *n == username || *n == password) {
+                db.remove(name)?;
+            }
+            Ok(String::new())
+        }
+        _ => Ok(String::new()),
+    }
+}
+
+/// A `key=value` line of the credential helper protocol, which has no way to escape newlines.
+fn line(key: &str, value: &str) -> Result<String> {
+    match value.contains(&['\n', '\0'][..]) {
+        true => Err(Error::msg(format!("credential {}s cannot hold newlines or NUL characters", key))),
+        false => Ok(format!("{}={}\n", key, value)),
+    }
+}
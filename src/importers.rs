@@ -0,0 +1,342 @@
+//! Import of plaintext secrets from other tools' files.
+//!
+//! Files are first [`read`][1] into secrets, which are then [`import`][2]ed into the database
+//! through [`Database::store`][3], the same way `hips store` would.
+//!
+//! [1]: fn.read.html
+//! [2]: fn.import.html
+//! [3]: ../struct.Database.html#method.store
+
+use crate::prelude::*;
+use ::std::path::Path;
+
+/// The format of a plaintext file to import.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `NAME=value` lines as read by dotenv libraries: comments, `export` prefixes, single and
+    /// double quotes (the latter with escapes), possibly spanning multiple lines.
+    Dotenv,
+    /// `NAME=value` lines taken literally, as in the output of `env` or docker's `--env-file`.
+    Env,
+    /// A JSON object, nested objects giving hierarchical names.
+    Json,
+    /// A YAML mapping, nested mappings giving hierarchical names.
+    Yaml,
+    /// A [password-store][1] folder, `web/github.gpg` giving `web/github`. Files are decrypted
+    /// using the local `gpg` binary, those without the `.gpg` extension are taken as already
+    /// decrypted exports.
+    ///
+    /// [1]: https://www.passwordstore.org/
+    Pass,
+    /// A YAML or JSON file decrypted by [sops][1] (`sops -d`), read as `Yaml`.
+    ///
+    /// [1]: https://github.com/getsops/sops
+    Sops,
+}
+
+impl Format {
+    /// Guess the format from a file's extension, folders being password stores.
+    pub fn guess(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            _ if path.is_dir() => Some(Self::Pass),
+            _ if name == ".env" || name.starts_with(".env.") => Some(Self::Dotenv),
+            Some("env") => Some(Self::Dotenv),
+            Some("json") => Some(Self::Json),
+            Some("yaml") | Some("yml") => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+}
+
+impl ::std::str::FromStr for Format {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dotenv" => Ok(Self::Dotenv),
+            "env" => Ok(Self::Env),
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            "pass" => Ok(Self::Pass),
+            "sops" => Ok(Self::Sops),
+            _ => Err(Error::msg(format!("unsupported format: {}", s))),
+        }
+    }
+}
+
+/// What to do when an imported secret already exists in the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conflicts {
+    /// Import nothing, failing with the list of existing secrets.
+    Fail,
+    /// Replace the existing secrets.
+    Overwrite,
+    /// Keep the existing secrets.
+    Skip,
+}
+
+/// The outcome of an [`import`][1].
+///
+/// [1]: fn.import.html
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    /// Secrets which did not exist.
+    pub added: Vec<String>,
+    /// Existing secrets which were replaced.
+    pub overwritten: Vec<String>,
+    /// Existing secrets which were kept.
+    pub skipped: Vec<String>,
+}
+
+/// Read the file or folder at `path` in `format` into secrets.
+///
+/// Secrets must appear only once.
+pub fn read(path: &Path, format: Format) -> Result<Vec<Secret>> {
+    read_with(path, format, None)
+}
+
+/// Same as [`read`][1], decrypting password stores with the gpg keys of `gnupg_home` rather
+/// than the default ones.
+///
+/// [1]: fn.read.html
+pub fn read_with(path: &Path, format: Format, gnupg_home: Option<&Path>) -> Result<Vec<Secret>> {
+    match format {
+        Format::Pass => {
+            let mut secrets = vec![];
+            pass(path, "", gnupg_home, &mut secrets)?;
+            unique(secrets)
+        }
+        _ => parse(&::std::fs::read_to_string(path).context("reading file")?, format),
+    }
+}
+
+/// Parse the plaintext `content` of a file in `format` into secrets.
+///
+/// Secrets must appear only once. Password stores are folders, they can only be [`read`][1].
+///
+/// [1]: fn.read.html
+pub fn parse(content: &str, format: Format) -> Result<Vec<Secret>> {
+    unique(match format {
+        Format::Dotenv => dotenv(content),
+        Format::Env => content.lines().enumerate().filter(|(_, line)| {
+            !line.trim().is_empty() && !line.trim_start().starts_with('#')
+        }).map(|(number, line)| {
+            let (name, value) = line.split_once('=')
+                .with_context(|| format!("line {}: expected NAME=value", number + 1))?;
+            Ok(secret(name.trim(), value))
+        }).collect(),
+        Format::Json => {
+            flatten(::serde_json::from_str(content).context("parsing json")?)
+        }
+        Format::Yaml => {
+            flatten(::serde_yaml::from_str(content).context("parsing yaml")?)
+        }
+        Format::Pass => Err(Error::msg("password stores are folders")),
+        Format::Sops => {
+            let value: ::serde_json::Value = ::serde_yaml::from_str(content)
+                .context("parsing yaml")?;
+            // Encrypted files hold sops' metadata under a top-level key, decrypted ones do not.
+            let mut data = value.clone();
+            if let Some(object) = data.as_object_mut() {
+                object.remove("sops");
+            }
+            match flatten(data)?.iter().find(|secret| secret.secret.starts_with("ENC[")) {
+                Some(secret) => Err(Error::msg(format!(
+                    "{} is encrypted, decrypt the file using sops -d first", secret.name,
+                ))),
+                None => flatten(value),
+            }
+        }
+    }?)
+}
+
+/// Store `secrets` in `db`, handling the existing ones according to `conflicts`.
+///
+/// With `dry_run`, nothing is stored: the summary tells what an actual import would do.
+pub fn import(
+    db: &mut Database,
+    secrets: Vec<Secret>,
+    conflicts: Conflicts,
+    dry_run: bool,
+) -> Result<Summary> {
+    let existing = db.list_names("")?.into_iter().collect::<::std::collections::BTreeSet<_>>();
+    let mut summary = Summary::default();
+    let mut stored = vec![];
+    for secret in secrets {
+        crate::database::validate(&secret.name)?;
+        match (existing.contains(&secret.name), conflicts) {
+            (false, _) => summary.added.push(secret.name.clone()),
+            (true, Conflicts::Skip) => {
+                summary.skipped.push(secret.name);
+                continue;
+            }
+            (true, _) => summary.overwritten.push(secret.name.clone()),
+        }
+        stored.push(secret);
+    }
+    if conflicts == Conflicts::Fail && !summary.overwritten.is_empty() {
+        return Err(Error::msg(format!(
+            "secrets already exist: {}", summary.overwritten.join(", "),
+        )));
+    }
+
+    if !dry_run {
+        for secret in stored {
+            let name = secret.name.clone();
+            db.store(secret).with_context(|| format!("storing {}", name))?;
+        }
+    }
+    Ok(summary)
+}
+
+fn unique(secrets: Vec<Secret>) -> Result<Vec<Secret>> {
+    let mut names = ::std::collections::BTreeSet::new();
+    match secrets.iter().find(|secret| !names.insert(&secret.name)) {
+        Some(secret) => Err(Error::msg(format!("{} appears more than once", secret.name))),
+        None => Ok(secrets),
+    }
+}
+
+fn secret(name: &str, value: &str) -> Secret {
+    Secret { name: name.to_owned(), secret: value.to_owned(), ..Default::default() }
+}
+
+fn dotenv(content: &str) -> Result<Vec<Secret>> {
+    let mut secrets = vec![];
+    let mut lines = content.lines().enumerate();
+    while let Some((number, line)) = lines.next() {
+        let line = line.trim_start();
+        if line.trim_end().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = line.split_once('=')
+            .with_context(|| format!("line {}: expected NAME=value", number + 1))?;
+        let mut value = value.trim_start().to_owned();
+
+        let secret = match value.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => loop {
+                if let Some((secret, rest)) = unquote(&value[1..], quote) {
+                    if !rest.trim().is_empty() && !rest.trim_start().starts_with('#') {
+                        return Err(Error::msg(format!(
+                            "line {}: unexpected content after the closing quote", number + 1,
+                        )));
+                    }
+                    break secret;
+                }
+                let (_, next) = lines.next()
+                    .with_context(|| format!("line {}: unterminated quote", number + 1))?;
+                value.push('\n');
+                value.push_str(next);
+            },
+            _ => value.split(" #").next().unwrap_or_default().trim_end().to_owned(),
+        };
+        secrets.push(self::secret(name.trim(), &secret));
+    }
+    Ok(secrets)
+}
+
+fn pass(dir: &Path, prefix: &str, home: Option<&Path>, secrets: &mut Vec<Secret>) -> Result<()> {
+    let mut entries = ::std::fs::read_dir(dir)
+        .with_context(|| format!("reading {}", dir.display()))?
+        .collect::<::std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let file_name = entry.file_name().into_string()
+            .map_err(|name| Error::msg(format!("invalid file name: {:?}", name)))?;
+        if file_name.starts_with('.') {
+            continue;
+        }
+        let name = match prefix.is_empty() {
+            true => file_name,
+            false => format!("{}/{}", prefix, file_name),
+        };
+        if path.is_dir() {
+            pass(&path, &name, home, secrets)?;
+            continue;
+        }
+
+        let (name, content) = match name.strip_suffix(".gpg") {
+            Some(name) => (name, gpg(&path, home)?),
+            None => (&name[..], ::std::fs::read_to_string(&path)
+                .with_context(|| format!("reading {}", path.display()))?),
+        };
+        // pass always terminates entries with a newline.
+        secrets.push(secret(name, content.strip_suffix('\n').unwrap_or(&content)));
+    }
+    Ok(())
+}
+
+fn gpg(path: &Path, home: Option<&Path>) -> Result<String> {
+    let mut gpg = ::std::process::Command::new("gpg");
+    if let Some(home) = home {
+        gpg.env("GNUPGHOME", home);
+    }
+    let output = gpg.args(["--quiet", "--batch", "--use-agent", "--decrypt"])
+        .arg(path)
+        .output()
+        .context("running gpg")?;
+    match output.status.success() {
+        true => String::from_utf8(output.stdout).context("decoding decrypted file"),
+        false => Err(Error::msg(String::from_utf8_lossy(&output.stderr).trim().to_owned())),
+    }.with_context(|| format!("decrypting {}", path.display()))
+}
+
+/// Split a quoted dotenv value from the rest of its line, `None` if the quote is not closed.
+///
+/// Single quotes are literal, double quotes support the `\n`, `\r`, `\t`, `\"`, `\\` and `\$`
+/// escapes.
+fn unquote(value: &str, quote: char) -> Option<(String, &str)> {
+    let mut unquoted = String::new();
+    let mut chars = value.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Some((unquoted, &value[i + 1..])),
+            '\\' if quote == '"' => match chars.next()?.1 {
+                'n' => unquoted.push('\n'),
+                'r' => unquoted.push('\r'),
+                't' => unquoted.push('\t'),
+                c @ ('"' | '\\' | '$') => unquoted.push(c),
+                c => {
+                    unquoted.push('\\');
+                    unquoted.push(c);
+                }
+            },
+            c => unquoted.push(c),
+        }
+    }
+    None
+}
+
+/// Turn an object into secrets, nested objects giving hierarchical names.
+fn flatten(value: ::serde_json::Value) -> Result<Vec<Secret>> {
+    fn walk(name: String, value: ::serde_json::Value, secrets: &mut Vec<Secret>) -> Result<()> {
+        use ::serde_json::Value;
+        let value = match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    let key = match name.is_empty() {
+                        true => key,
+                        false => format!("{}/{}", name, key),
+                    };
+                    walk(key, value, secrets)?;
+                }
+                return Ok(());
+            }
+            _ if name.is_empty() => return Err(Error::msg("expected an object")),
+            Value::String(value) => value,
+            Value::Number(value) => value.to_string(),
+            Value::Bool(value) => value.to_string(),
+            Value::Null | Value::Array(_) => return Err(Error::msg(format!(
+                "{}: expected a string, a number, a boolean or an object", name,
+            ))),
+        };
+        secrets.push(secret(&name, &value));
+        Ok(())
+    }
+
+    let mut secrets = vec![];
+    walk(String::new(), value, &mut secrets)?;
+    Ok(secrets)
+}
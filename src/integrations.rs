@@ -0,0 +1,174 @@
+//! Protocols through which other tools read secrets from the database.
+
+use crate::prelude::*;
+use ::std::collections::BTreeMap;
+
+/// Answer a Terraform [`external`][1] data source `query`, in JSON.
+///
+/// The query holds `names`, a comma-separated list of secrets returned under their names, and/or
+/// `prefix`, whose secrets are returned named relatively to it. The result is a flat JSON object
+/// of the secrets' values, two secrets returned under the same key being an error.
+///
+/// [1]: https://registry.terraform.io/providers/hashicorp/external/latest/docs/data-sources/external
+pub fn terraform_data(db: &Database, query: &str) -> Result<String> {
+    let query: BTreeMap<String, String> = ::serde_json::from_str(query)
+        .context("parsing query, expected an object of strings")?;
+    if query.is_empty() {
+        return Err(Error::msg("the query needs names or a prefix"));
+    }
+
+    let mut result = BTreeMap::new();
+    let mut insert = |key: String, value: String| match result.insert(key.clone(), value) {
+        Some(_) => Err(Error::msg(format!("several secrets are returned as {}", key))),
+        None => Ok(()),
+    };
+    for (key, value) in query {
+        match key.as_str() {
+            "names" => for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                let secret = db.load(name.to_owned()).with_context(|| format!("loading {}", name))?;
+                insert(secret.name, secret.secret)?;
+            },
+            "prefix" => {
+                let prefix = value.trim_end_matches('/');
+                for secret in db.list_scope(prefix)? {
+                    let name = secret.name[prefix.len()..].trim_start_matches('/').to_owned();
+                    insert(name, secret.secret)?;
+                }
+            }
+            _ => return Err(Error::msg(format!("unsupported query key: {}", key))),
+        }
+    }
+    Ok(::serde_json::to_string(&result)?)
+}
+
+/// Print AWS credentials as expected from a [`credential_process`][1].
+///
+/// `access_key`, `secret_key` and `session_token` are the names of the secrets holding them.
+/// Session tokens being temporary, they need the name of the secret holding their `expiration`
+/// time, in ISO 8601 format.
+///
+/// [1]: https://docs.aws.amazon.com/cli/latest/userguide/cli-configure-sourcing-external.html
+pub fn aws_credentials(
+    db: &Database,
+    access_key: &str,
+    secret_key: &str,
+    session_token: Option<&str>,
+    expiration: Option<&str>,
+) -> Result<String> {
+    let load = |name: &str| -> Result<String> {
+        Ok(db.load(name.to_owned()).with_context(|| format!("loading {}", name))?.secret)
+    };
+    if session_token.is_some() && expiration.is_none() {
+        return Err(Error::msg("session tokens need an expiration"));
+    }
+    Ok(::serde_json::to_string(&AwsCredentials {
+        version: 1,
+        access_key_id: load(access_key)?,
+        secret_access_key: load(secret_key)?,
+        session_token: session_token.map(load).transpose()?,
+        expiration: expiration.map(load).transpose()?,
+    })?)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct AwsCredentials {
+    version: u8,
+    access_key_id: String,
+    secret_access_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration: Option<String>,
+}
+
+/// The message docker expects from credential helpers when they have no credentials.
+pub const DOCKER_NOT_FOUND: &str = "credentials not found in native keychain";
+
+/// Answer a [docker credential helper][1] request: `action` is `get`, `store`, `erase` or
+/// `list`, `input` what docker wrote to the helper's standard input.
+///
+/// A registry's credentials are stored as `docker/<server>/username` and `docker/<server>/secret`
+/// (as in `docker/index.docker.io/v1/secret`), along with its URL as `docker/<server>/url`.
+/// `None` means the credentials were not found, see [`DOCKER_NOT_FOUND`][2].
+///
+/// [1]: https://github.com/docker/docker-credential-helpers
+/// [2]: constant.DOCKER_NOT_FOUND.html
+pub fn docker_credential(db: &mut Database, action: &str, input: &str) -> Result<Option<String>> {
+    let name = |url: &str| {
+        let server = url.trim().split_once("://").map(|(_, server)| server).unwrap_or(url.trim());
+        let segments = server.split('/').filter(|segment| !segment.is_empty());
+        ::std::iter::once(DOCKER_PREFIX).chain(segments).collect::<Vec<_>>().join("/")
+    };
+    let names = |name: &str| ["url", "username", "secret"].map(|key| format!("{}/{}", name, key));
+
+    match action {
+        "store" => {
+            let credentials: DockerCredentials = ::serde_json::from_str(input)
+                .context("parsing credentials")?;
+            let names = names(&name(&credentials.server_url));
+            let values = [credentials.server_url, credentials.username, credentials.secret];
+            for (name, secret) in names.iter().cloned().zip(values) {
+                db.store(Secret { name, secret, ..Default::default() })?;
+            }
+            Ok(Some(String::new()))
+        }
+        "get" => {
+            let name = name(input);
+            let [url, username, secret] = names(&name);
+            let existing = db.list_names(&name)?;
+            if !existing.contains(&username) || !existing.contains(&secret) {
+                return Ok(None);
+            }
+            Ok(Some(::serde_json::to_string(&DockerCredentials {
+                server_url: match existing.contains(&url) {
+                    true => db.load(url)?.secret,
+                    false => input.trim().to_owned(),
+                },
+                username: db.load(username)?.secret,
+                secret: db.load(secret)?.secret,
+            })?))
+        }
+        "erase" => {
+            let name = name(input);
+            let names = names(&name);
+            let names = db.list_names(&name)?.into_iter().filter(|n| {
+                names.contains(n)
+            }).collect::<Vec<_>>();
+            if names.is_empty() {
+                return Ok(None);
+            }
+            for name in names {
+                db.remove(name)?;
+            }
+            Ok(Some(String::new()))
+        }
+        "list" => {
+            let existing = db.list_names(DOCKER_PREFIX)?;
+            let mut servers = BTreeMap::new();
+            for name in &existing {
+                if let Some(name) = name.strip_suffix("/username") {
+                    let [url, username, _] = names(name);
+                    let url = match existing.contains(&url) {
+                        true => db.load(url)?.secret,
+                        false => name[DOCKER_PREFIX.len() + 1..].to_owned(),
+                    };
+                    servers.insert(url, db.load(username)?.secret);
+                }
+            }
+            Ok(Some(::serde_json::to_string(&servers)?))
+        }
+        _ => Err(Error::msg(format!("unsupported action: {}", action))),
+    }
+}
+
+const DOCKER_PREFIX: &str = "docker";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerCredentials {
+    #[serde(rename = "ServerURL")]
+    server_url: String,
+    username: String,
+    secret: String,
+}
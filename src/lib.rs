@@ -13,8 +13,8 @@ extern crate serde;
 
 use crate::prelude::*;
 mod prelude {
-    pub use crate::{Backend, Database, Encrypter};
-    pub use crate::{Encrypted, Secret};
+    pub use crate::{Backend, Change, Database, Encrypter, Engine};
+    pub use crate::{Encrypted, Manifest, Pattern, Scope, Secret, Verification};
     pub use anyhow::{Context, Error, Result};
     pub use std::io::{Read, Write};
     pub use std::path::PathBuf;
@@ -30,6 +30,7 @@ pub struct Database {
     e: Box<dyn Encrypter>,
 }
 mod database;
+mod formatters;
 
 /// Storage behavior: what does it mean to store/load/..?
 ///
@@ -44,34 +45,110 @@ pub trait Backend {
     fn load(&self, name: String) -> Result<Encrypted>;
     fn remove(&mut self, name: String) -> Result<()>;
     fn list(&self) -> Result<Vec<Encrypted>>;
+
+    /// Load the integrity [`Manifest`][1], if any was stored.
+    ///
+    /// Backends which do not support manifests can rely on the default implementations, in which
+    /// case no integrity checks are performed. Those which do must say so through
+    /// `supports_manifest`.
+    ///
+    /// [1]: struct.Manifest.html
+    fn load_manifest(&self) -> Result<Option<Manifest>> {
+        Ok(None)
+    }
+    fn store_manifest(&mut self, _manifest: Manifest) -> Result<()> {
+        Ok(())
+    }
+    fn supports_manifest(&self) -> bool {
+        false
+    }
 }
 pub mod backends;
 
 /// Encryption behavior: what does it mean to encrypt/decrypt?
 ///
-/// A single [`Ring`][1] encrypter is available at this time. In a past version, an openssl option
-/// was also available. You are free to implement your own `Encrypter` and initialize a new
-/// `Database` with it.
+/// Two encrypters are available at this time: [`Ring`][1] (the default) and [`Age`][2], which
+/// [`Scoped`][3] can combine to encrypt parts of a database with different keys. In a past
+/// version, an openssl option was also available. You are free to implement your own `Encrypter`
+/// and initialize a new `Database` with it.
 ///
 /// [1]: encrypters/struct.Ring.html
+/// [2]: encrypters/struct.Age.html
+/// [3]: encrypters/struct.Scoped.html
 pub trait Encrypter {
     fn encrypt(&self, secret: Secret) -> Result<Encrypted>;
     fn decrypt(&self, encrypted: Encrypted) -> Result<Secret>;
+
+    /// The key scopes of this encrypter, if it has any.
+    fn scopes(&self) -> Vec<Scope> {
+        vec![]
+    }
+
+    /// Whether this encrypter can only encrypt, as with age recipients and no identities.
+    fn write_only(&self) -> bool {
+        false
+    }
 }
 pub mod encrypters;
 
-/// A plaintext secret and its name.
+/// Templating behavior: how are secrets inserted in templates?
+///
+/// Two engines are available: [`TinyTemplate`][1] (the default) and [`Jinja`][2]. `context` is
+/// the serialized template context, see [`Database::template`][3] for its content.
+///
+/// [1]: engines/struct.TinyTemplate.html
+/// [2]: engines/struct.Jinja.html
+/// [3]: struct.Database.html#method.template
+pub trait Engine {
+    fn render(&self, template: &str, context: &::serde_json::Value) -> Result<String>;
+}
+pub mod engines;
+
+pub mod exporters;
+
+pub mod git;
+
+pub mod importers;
+
+pub mod integrations;
+
+pub mod recovery;
+
+/// A part of the database encrypted under its own key, see [`Scoped`][1].
+///
+/// [1]: encrypters/struct.Scoped.html
+#[derive(Clone, Debug)]
+pub struct Scope {
+    /// Secrets named after this prefix belong to the scope, the default scope's prefix is empty.
+    pub prefix: String,
+    /// Whether the key of this scope is available.
+    pub unlocked: bool,
+}
+
+/// A plaintext secret, its name and metadata.
+///
+/// The `description` and `tags` metadata are not encrypted, unless names are opaque (see
+/// [`Database::set_opaque_names`][3].)
 ///
 /// Returned by the `decrypt` method of an [`Encrypter`][1] when provided an [`Encrypted`][2]
 /// secret. Passing this secret to the same encrypter's `encrypt` method again might yield
 /// different `Encrypted` data (this depends on the encrypter implementation.)
 ///
+/// Fields may be added over time: use [`Secret::new`][4], or `..Default::default()` when
+/// building a `Secret` from its fields.
+///
 /// [1]: trait.Encrypter.html
 /// [2]: struct.Encrypted.html
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// [3]: struct.Database.html#method.set_opaque_names
+/// [4]: struct.Secret.html#method.new
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Secret {
     pub name: String,
     pub secret: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
 }
 
 /// An encrypted secret and its name.
@@ -81,9 +158,105 @@ pub struct Secret {
 ///
 /// [1]: trait.Encrypter.html
 /// [2]: struct.Secret.html
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Encrypted {
     name: String,
     secret: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     salt: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    cipher: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+}
+
+/// A keyed MAC over the whole database.
+///
+/// The manifest covers the set of secret names, a hash of every encrypted secret and a revision
+/// number, increased on every change. It is maintained by the [`Database`][1] on every store and
+/// remove, and verified on every read. This detects entries being deleted, added or rolled back
+/// individually by someone without access to the secrets.
+///
+/// The manifest also records whether secret names are opaque (see
+/// [`Database::set_opaque_names`][2].)
+///
+/// The MAC key is random and stored in the database itself, encrypted using the database's
+/// `Encrypter` under a name no secret can have, along with the opaque mode. It records that the
+/// database has a manifest: once the key exists, a missing manifest fails every read instead of
+/// disabling the checks, and re-creating the manifest keeps names opaque.
+/// Writers without access to the key (age recipients only) can still store secrets, but not
+/// update the manifest: their changes have to be accepted by someone with the key (see
+/// [`Database::reset_manifest`][3].) Note that with public-key encrypters, anyone knowing the
+/// recipients can forge a manifest, the same way they can store secrets.
+///
+/// With key scopes (see [`Scoped`][4]), the manifest has a section per scope, each with its own
+/// key, revision and MAC. Those with the key of a scope only verify and sign its section.
+///
+/// [1]: struct.Database.html
+/// [2]: struct.Database.html#method.set_opaque_names
+/// [3]: struct.Database.html#method.reset_manifest
+/// [4]: encrypters/struct.Scoped.html
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    revision: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    opaque: bool,
+    entries: ::std::collections::BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "::std::collections::BTreeMap::is_empty")]
+    fingerprints: ::std::collections::BTreeMap<String, String>,
+    mac: String,
+    #[serde(default, skip_serializing_if = "::std::collections::BTreeMap::is_empty")]
+    scopes: ::std::collections::BTreeMap<String, Manifest>,
+    #[serde(skip)]
+    key: Option<Vec<u8>>,
+    #[serde(skip)]
+    changed: bool,
+}
+mod manifest;
+
+/// The result of verifying a database against its [`Manifest`][1].
+///
+/// [1]: struct.Manifest.html
+#[derive(Clone, Debug, Default)]
+pub struct Verification {
+    /// Revision of the manifest, `None` if the database has no manifest. With key scopes, this
+    /// is the sum of the revisions of the sections verified.
+    pub revision: Option<u64>,
+    /// Whether the manifest's MAC is valid.
+    pub authentic: bool,
+    /// Secrets listed in the manifest but absent from the database.
+    pub missing: Vec<String>,
+    /// Secrets present in the database but absent from the manifest.
+    pub unexpected: Vec<String>,
+    /// Secrets whose encrypted content does not match the manifest.
+    pub modified: Vec<String>,
+}
+
+/// A difference between two databases, see [`Database::diff`][1].
+///
+/// [1]: struct.Database.html#method.diff
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// A secret only present in the other database.
+    Added(Secret),
+    /// A secret only present in this database.
+    Removed(Secret),
+    /// A secret whose value or metadata differ, as in this database and in the other one.
+    Changed(Secret, Secret),
+}
+
+/// A search through the database, see [`Database::find`][1].
+///
+/// Matches secrets whose name or description matches a glob or a regex, and which carry all the
+/// required tags. Optionally, the decrypted values can be searched too.
+///
+/// [1]: struct.Database.html#method.find
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    regex: ::regex::Regex,
+    tags: Vec<String>,
+    values: bool,
 }
+mod pattern;
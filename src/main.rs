@@ -1,6 +1,7 @@
 #[macro_use]
 extern crate clap;
 
+use ::anyhow::Context;
 use ::clap::Parser;
 use ::clishe::prelude::*;
 use ::std::io::Write;
@@ -14,9 +15,135 @@ fn main() -> Result<()> {
 }
 
 fn run() -> Result<()> {
-    let db_path = unwrap_env_var("HIPS_DATABASE")?.into();
-    let password = unwrap_env_var("HIPS_PASSWORD")?;
-    Hips::parse().run(&mut ::hips::Database::from_file(db_path, password)?)
+    let mut args = ::std::env::args_os().collect::<Vec<_>>();
+    // Docker runs `docker-credential-<credsStore> <action>`, symlinking hips under that name works.
+    let program = args.first().map(::std::path::Path::new).and_then(|path| path.file_name());
+    if program.map(|name| name == "docker-credential-hips").unwrap_or(false) {
+        args.splice(0..1, vec!["hips".into(), "docker-credential".into()]);
+    }
+    let hips = Hips::parse_from(args);
+    let db = unwrap_env_var("HIPS_DATABASE").and_then(|db_path| open(db_path.into()));
+    // Some commands (git drivers...) work without the database, only fail when it is used.
+    hips.run(&mut db.unwrap_or_else(|err| {
+        let err = format!("{:#}", err);
+        ::hips::Database::new(Box::new(Unavailable(err.clone())), Box::new(Unavailable(err)))
+    }))
+}
+
+/// Open the database, using the cipher it records unless `HIPS_CIPHER` overrides it.
+fn open(path: ::std::path::PathBuf) -> Result<hips::Database> {
+    let db = ::hips::Database::open(path.clone(), encrypter("HIPS", None, None)?)?;
+    match database_cipher(&db)? {
+        Some(cipher) => ::hips::Database::open(path, encrypter("HIPS", None, Some(cipher))?),
+        None => Ok(db),
+    }
+}
+
+/// The ring cipher recorded by the database, `None` if `HIPS_CIPHER` is set.
+fn database_cipher(db: &hips::Database) -> Result<Option<hips::encrypters::Cipher>> {
+    if ::std::env::var("HIPS_CIPHER").is_ok() {
+        return Ok(None);
+    }
+    // Other encrypters record no cipher, or one of their own.
+    Ok(db.cipher()?.and_then(|cipher| cipher.parse().ok()))
+}
+
+/// The encrypter configured by the `<vars>_*` environment variables, key scopes included.
+///
+/// Only `HIPS` falls back to the password recovered from `HIPS_RECOVERY_SHARES`.
+fn encrypter(
+    vars: &str,
+    password: Option<String>,
+    cipher: Option<hips::encrypters::Cipher>,
+) -> Result<Box<dyn hips::Encrypter>> {
+    let var = |name: &str| ::std::env::var(format!("{}_{}", vars, name));
+    let password = match password.or_else(|| var("PASSWORD").ok()) {
+        None if vars == "HIPS" => recovered_password()?,
+        password => password,
+    };
+    let default = configured_encrypter(vars, password, cipher)?;
+
+    let scopes = match var("SCOPES") {
+        Ok(scopes) => ::std::fs::read_to_string(scopes).context("reading scopes file")?,
+        Err(_) => return default.map(Ok).unwrap_or_else(|| {
+            unwrap_env_var(&format!("{}_PASSWORD", vars))
+                .and(Err(Error::msg("no password available")))
+        }),
+    };
+    let mut scoped = hips::encrypters::Scoped::new(default);
+    for line in scopes.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        let (prefix, vars) = line.split_once(char::is_whitespace)
+            .with_context(|| format!("expected '<prefix> <variables prefix>': {}", line))?;
+        scoped = scoped.with_scope(prefix.to_owned(), configured_encrypter(
+            vars.trim(), ::std::env::var(format!("{}_PASSWORD", vars.trim())).ok(), None,
+        ).with_context(|| format!("configuring scope {}", prefix))?);
+    }
+    Ok(Box::new(scoped))
+}
+
+/// The encrypter configured by the `<vars>_ENCRYPTER`, `<vars>_CIPHER`... environment variables.
+///
+/// `None` if it needs a password and none was provided.
+fn configured_encrypter(
+    vars: &str,
+    password: Option<String>,
+    cipher: Option<hips::encrypters::Cipher>,
+) -> Result<Option<Box<dyn hips::Encrypter>>> {
+    let var = |name: &str| ::std::env::var(format!("{}_{}", vars, name));
+    match var("ENCRYPTER").as_deref().unwrap_or("ring") {
+        "ring" => {
+            let cipher = match cipher {
+                Some(cipher) => cipher,
+                None => var("CIPHER").as_deref().unwrap_or("aes-256-gcm").parse()?,
+            };
+            Ok(password.map(|password| {
+                Box::new(hips::encrypters::Ring::with_cipher(password, cipher)) as Box<_>
+            }))
+        }
+        "age" => match var("AGE_RECIPIENTS") {
+            Ok(recipients) => Ok(Some(Box::new(hips::encrypters::Age::with_recipients(
+                &::std::fs::read_to_string(&recipients).context("reading recipients file")?,
+                &match var("AGE_IDENTITY") {
+                    Ok(identity) => ::std::fs::read_to_string(identity)
+                        .context("reading identity file")?,
+                    Err(_) => String::new(),
+                },
+            )?))),
+            Err(_) => Ok(password.map(|password| {
+                Box::new(hips::encrypters::Age::with_passphrase(password)) as Box<_>
+            })),
+        },
+        "ssh" => Ok(Some(Box::new(hips::encrypters::Age::with_ssh_keys(
+            &::std::fs::read_to_string(var("SSH_RECIPIENTS")
+                .with_context(|| format!("{}_SSH_RECIPIENTS", vars))?)
+                .context("reading recipients file")?,
+            // Without a private key, the database can only be written to.
+            &match var("SSH_IDENTITY") {
+                Ok(identity) => ::std::fs::read_to_string(identity)
+                    .context("reading private key file")?,
+                Err(_) => match ::std::env::var("HOME").map(|home| {
+                    ::std::path::PathBuf::from(home).join(".ssh/id_ed25519")
+                }) {
+                    Ok(path) if path.exists() => ::std::fs::read_to_string(path)
+                        .context("reading private key file")?,
+                    _ => String::new(),
+                },
+            },
+            password,
+        )?))),
+        other => Err(Error::msg(format!("unsupported encrypter: {}", other))),
+    }
+}
+
+fn recovered_password() -> Result<Option<String>> {
+    match ::std::env::var("HIPS_RECOVERY_SHARES") {
+        Ok(path) => Ok(Some(hips::recovery::combine(
+            &::std::fs::read_to_string(path).context("reading shares file")?.lines().filter(|l| {
+                !l.trim().is_empty()
+            }).map(str::to_owned).collect::<Vec<_>>(),
+        ).context("recovering password from shares")?)),
+        Err(_) => Ok(None),
+    }
 }
 
 dispatchers! {
@@ -27,29 +154,83 @@ dispatchers! {
         version = env!("CARGO_PKG_VERSION"),
         after_help = "\
             ENVIRONMENT:\n    \
-            HIPS_DATABASE    File/folder containing the secrets (mandatory)\n    \
-            HIPS_PASSWORD    Password that will unlock the database (mandatory)\
+            HIPS_DATABASE          File/folder containing the secrets (mandatory)\n    \
+            HIPS_PASSWORD          Password that will unlock the database\n    \
+            HIPS_RECOVERY_SHARES   File of recovery shares, used if HIPS_PASSWORD is unset\n    \
+            HIPS_ENCRYPTER         Encryption scheme: ring (default), age or ssh\n    \
+            HIPS_CIPHER            Cipher: aes-256-gcm or chacha20-poly1305, the database's \
+                                   by default (ring only)\n    \
+            HIPS_AGE_RECIPIENTS    File listing the age recipients (age only)\n    \
+            HIPS_AGE_IDENTITY      File containing the age identities (age only)\n    \
+            HIPS_SSH_RECIPIENTS    authorized_keys file listing the recipients (ssh only)\n    \
+            HIPS_SSH_IDENTITY      Private key file, ~/.ssh/id_ed25519 if any (ssh only)\n    \
+            HIPS_SCOPES            File of '<prefix> <variables prefix>' key scopes, see README\
         ",
     )]
     Hips(self, _: &mut hips::Database) -> Result<()> [
         Store: commands::Store,
         Load: commands::Load,
         List: commands::List,
+        Find: commands::Find,
         Remove: commands::Remove,
         Rename: commands::Rename,
+        Exec: commands::Exec,
         Rotate: commands::Rotate,
         Template: commands::Template,
+        Render: commands::Render,
+        Verify: commands::Verify,
+        Opaque: commands::Opaque,
+        Recovery: commands::Recovery,
+        Import: commands::Import,
+        Export: commands::Export,
+        Diff: commands::Diff,
+        TerraformData: commands::TerraformData,
+        AwsCredentials: commands::AwsCredentials,
+        GitSetup: commands::GitSetup,
+        GitMergeDriver: commands::GitMergeDriver,
+        GitTextconv: commands::GitTextconv,
+        GitCredential: commands::GitCredential,
+        DockerCredential: commands::DockerCredential,
     ],
 }
 mod commands;
 
 fn unwrap_env_var(name: &str) -> Result<String> {
-    let var = ::std::env::var(name).map_err(|err| Error::msg(format!("{}: {}", err, name)));
+    ::std::env::var(name).map_err(|err| Error::msg(format!("{}: {}", err, name)))
+}
+
+/// Stands in for the database when it could not be opened, failing with the reason on use.
+struct Unavailable(String);
+
+impl Unavailable {
+    fn err<T>(&self) -> Result<T> {
+        Err(Error::msg(self.0.clone())).context(
+            "hips expects both a database file/folder and its password to be provided as \
+            environment variables",
+        )
+    }
+}
 
-    if var.is_err() {
-        eprintln!("hips expects both a database file/folder and its");
-        eprintln!("password to be provided as environment variables");
+impl hips::Backend for Unavailable {
+    fn store(&mut self, _: hips::Encrypted) -> Result<()> {
+        self.err()
+    }
+    fn load(&self, _: String) -> Result<hips::Encrypted> {
+        self.err()
+    }
+    fn remove(&mut self, _: String) -> Result<()> {
+        self.err()
+    }
+    fn list(&self) -> Result<Vec<hips::Encrypted>> {
+        self.err()
     }
+}
 
-    var
+impl hips::Encrypter for Unavailable {
+    fn encrypt(&self, _: hips::Secret) -> Result<hips::Encrypted> {
+        self.err()
+    }
+    fn decrypt(&self, _: hips::Encrypted) -> Result<hips::Secret> {
+        self.err()
+    }
 }
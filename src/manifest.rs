@@ -0,0 +1,366 @@
+use crate::prelude::*;
+use ::base64::engine::general_purpose::STANDARD as BASE64;
+use ::base64::Engine;
+use ::ring::rand::SecureRandom;
+
+const KEY_LEN: usize = 32;
+/// The name the MAC key is stored under in the database, which no secret can have.
+pub(crate) const KEY_NAME: &str = ".manifest";
+
+/// The name of the MAC key of the manifest section of the scope under `prefix`.
+pub(crate) fn key_name(prefix: &str) -> String {
+    match prefix.trim_end_matches('/') {
+        "" => KEY_NAME.to_owned(),
+        prefix => format!("{}/{}", prefix, KEY_NAME),
+    }
+}
+
+/// Whether `name` is the MAC key of one of the manifest's sections.
+pub(crate) fn is_key(name: &str) -> bool {
+    name.rsplit('/').next() == Some(KEY_NAME)
+}
+
+/// The encrypted content of the key entry, which is authoritative for the opaque mode.
+#[derive(Serialize, Deserialize)]
+struct Key {
+    key: String,
+    opaque: bool,
+}
+
+impl Manifest {
+    /// Generate a new MAC key, for a section which has none yet.
+    pub(crate) fn generate_key(&mut self) -> Result<()> {
+        let mut key = vec![0u8; KEY_LEN];
+        ::ring::rand::SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|err| Error::msg(err.to_string()))?;
+        self.key = Some(key);
+        Ok(())
+    }
+
+    /// The revision of the manifest, increased on every change to the database.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Whether the secret names are opaque in the database.
+    pub fn opaque(&self) -> bool {
+        self.opaque
+    }
+
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.entries.iter()
+    }
+
+    pub(crate) fn set_opaque(&mut self, opaque: bool) {
+        self.opaque = opaque;
+        self.changed = true;
+    }
+
+    /// The section of the scope under `prefix`, this manifest itself for the default scope.
+    pub(crate) fn section(&self, prefix: &str) -> Option<&Manifest> {
+        match prefix {
+            "" => Some(self),
+            prefix => self.scopes.get(prefix),
+        }
+    }
+
+    pub(crate) fn section_mut(&mut self, prefix: &str) -> &mut Manifest {
+        match prefix {
+            "" => self,
+            prefix => self.scopes.entry(prefix.to_owned()).or_default(),
+        }
+    }
+
+    /// Drop the section of the scope under `prefix`, which no longer exists.
+    pub(crate) fn remove_section(&mut self, prefix: &str) {
+        self.scopes.remove(prefix);
+    }
+
+    /// The sections of the manifest and the prefix of their scope, the default one first.
+    pub(crate) fn sections(&self) -> impl Iterator<Item = (&str, &Manifest)> {
+        ::std::iter::once(("", self)).chain(self.scopes.iter().map(|(p, s)| (p.as_str(), s)))
+    }
+
+    /// Whether the section was ever signed, in which case its key must exist.
+    pub(crate) fn exists(&self) -> bool {
+        !self.mac.is_empty()
+    }
+
+    /// Whether the entries changed since the section was loaded.
+    pub(crate) fn changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Drop the sections which were never signed.
+    pub(crate) fn prune(&mut self) {
+        self.scopes.retain(|_, section| section.exists());
+    }
+
+    /// Whether the MAC key is available, see [`unlock`][1].
+    ///
+    /// [1]: #method.unlock
+    pub(crate) fn unlocked(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Decrypt the MAC key from its entry in the database, along with the opaque mode.
+    ///
+    /// The mode recorded in the manifest file is overridden: a manifest disagreeing with its key
+    /// fails verification.
+    pub(crate) fn unlock(&mut self, encrypter: &dyn Encrypter, key: &Encrypted) -> Result<()> {
+        let key = encrypter.decrypt(key.clone()).context("decrypting manifest key")?;
+        let key: Key = ::serde_json::from_str(&key.secret).context("unmarshalling manifest key")?;
+        self.key = Some(BASE64.decode(&key.key).context("decoding manifest key")?);
+        self.opaque = key.opaque;
+        Ok(())
+    }
+
+    /// The `name` entry of the MAC key and opaque mode in the database, encrypted by `encrypter`.
+    pub(crate) fn key_entry(&self, encrypter: &dyn Encrypter, name: &str) -> Result<Encrypted> {
+        let key = Key { key: BASE64.encode(self.key()?), opaque: self.opaque };
+        encrypter.encrypt(Secret {
+            name: name.to_owned(),
+            secret: ::serde_json::to_string(&key)?,
+            ..Default::default()
+        }).context("encrypting manifest key")
+    }
+
+    /// The name under which the `name` secret is stored in the backend.
+    ///
+    /// This is the name itself, or a keyed hash of it if names are opaque.
+    pub(crate) fn stored_name(&self, name: &str) -> Result<String> {
+        if !self.opaque {
+            return Ok(name.to_owned());
+        }
+
+        let key = self.derived_key(b"names")?;
+        Ok(hex(::ring::hmac::sign(&key, name.as_bytes()).as_ref()))
+    }
+
+    /// A keyed fingerprint of `secret`, which tells whether it changed.
+    ///
+    /// Unlike digests of the encrypted secrets, it does not change when the same secret is
+    /// encrypted again. Being keyed, it tells nothing about the secret without the key.
+    pub(crate) fn fingerprint(&self, secret: &Secret) -> Result<String> {
+        let mut ctx = ::ring::hmac::Context::with_key(&self.derived_key(b"fingerprints")?);
+        let tags = secret.tags.join("\n");
+        for field in &[&secret.name, &secret.secret, &secret.description, &tags] {
+            ctx.update(field.as_bytes());
+            ctx.update(b"\0");
+        }
+        Ok(format!("hmac:{}", hex(&ctx.sign().as_ref()[..8])))
+    }
+
+    /// The fingerprint of the `name` secret recorded in the section, if any.
+    pub(crate) fn recorded_fingerprint(&self, name: &str) -> Option<&String> {
+        self.fingerprints.get(name)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.fingerprints.clear();
+        self.changed = true;
+    }
+
+    /// Record `encrypted`, along with the `fingerprint` of the secret it encrypts if known.
+    pub(crate) fn insert(&mut self, encrypted: &Encrypted, fingerprint: Option<String>) {
+        self.entries.insert(encrypted.name.clone(), encrypted.digest());
+        match fingerprint {
+            Some(fingerprint) => self.fingerprints.insert(encrypted.name.clone(), fingerprint),
+            None => self.fingerprints.remove(&encrypted.name),
+        };
+        self.changed = true;
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) {
+        self.fingerprints.remove(name);
+        self.changed |= self.entries.remove(name).is_some();
+    }
+
+    /// Increase the revision and compute the MAC of the section.
+    pub(crate) fn sign(&mut self) -> Result<()> {
+        self.revision += 1;
+        self.mac = self.compute_mac()?;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Merge `theirs` version of the section into this one, given their common `base`, and sign
+    /// the result. Returns the names of the entries changed differently on both sides.
+    ///
+    /// Entries are merged the same way [`git::merge`][1] merges secrets, so that the result
+    /// covers the merged database. Both sides must be authentic.
+    ///
+    /// [1]: ../git/fn.merge.html
+    pub(crate) fn merge(&mut self, base: &Manifest, theirs: &Manifest) -> Result<Vec<String>> {
+        for side in &[&*self, theirs] {
+            if side.exists() && side.compute_mac()? != side.mac {
+                return Err(Error::msg("the manifest was modified without the key"));
+            }
+        }
+
+        let mut names = self.entries.keys().cloned().collect::<::std::collections::BTreeSet<_>>();
+        names.extend(theirs.entries.keys().cloned());
+        let mut conflicts = vec![];
+        for name in names {
+            let get = |section: &Manifest| section.entries.get(&name).cloned();
+            let (o, a, b) = (get(base), get(self), get(theirs));
+            let resolved = if a == b || b == o {
+                continue;
+            } else if a == o {
+                theirs
+            } else {
+                conflicts.push(name.clone());
+                continue;
+            };
+            match resolved.entries.get(&name) {
+                Some(digest) => self.entries.insert(name.clone(), digest.clone()),
+                None => self.entries.remove(&name),
+            };
+            match resolved.fingerprints.get(&name) {
+                Some(fingerprint) => self.fingerprints.insert(name, fingerprint.clone()),
+                None => self.fingerprints.remove(&name),
+            };
+        }
+        self.revision = self.revision.max(theirs.revision);
+        self.sign()?;
+        Ok(conflicts)
+    }
+
+    /// Take `theirs` version of the section if only they changed it since `base`, for sections
+    /// whose key is not available. `false` if both sides changed it.
+    pub(crate) fn take(&mut self, base: &Manifest, theirs: &Manifest) -> bool {
+        if self.mac == theirs.mac || theirs.mac == base.mac {
+            return true;
+        } else if self.mac != base.mac {
+            return false;
+        }
+        self.revision = theirs.revision;
+        self.opaque = theirs.opaque;
+        self.entries = theirs.entries.clone();
+        self.fingerprints = theirs.fingerprints.clone();
+        self.mac = theirs.mac.clone();
+        true
+    }
+
+    /// Check the section's MAC and compare its entries to the provided encrypted secrets.
+    pub(crate) fn verify(&self, encrypted: &[Encrypted]) -> Result<Verification> {
+        let actual = encrypted.iter().map(|e| {
+            (e.name.clone(), e.digest())
+        }).collect::<::std::collections::BTreeMap<_, _>>();
+
+        Ok(Verification {
+            revision: Some(self.revision),
+            authentic: self.compute_mac()? == self.mac,
+            missing: self.entries.keys().filter(|name| {
+                !actual.contains_key(*name)
+            }).cloned().collect(),
+            unexpected: actual.keys().filter(|name| {
+                !self.entries.contains_key(*name)
+            }).cloned().collect(),
+            modified: self.entries.iter().filter(|(name, digest)| {
+                actual.get(*name).map(|actual| actual != *digest).unwrap_or(false)
+            }).map(|(name, _)| name.clone()).collect(),
+        })
+    }
+
+    fn key(&self) -> Result<&[u8]> {
+        self.key.as_deref().context("the manifest key is not available")
+    }
+
+    /// A key derived from the MAC key, for another `purpose` than the MAC.
+    fn derived_key(&self, purpose: &[u8]) -> Result<::ring::hmac::Key> {
+        let key = ::ring::hmac::Key::new(::ring::hmac::HMAC_SHA256, self.key()?);
+        let key = ::ring::hmac::sign(&key, purpose);
+        Ok(::ring::hmac::Key::new(::ring::hmac::HMAC_SHA256, key.as_ref()))
+    }
+
+    fn compute_mac(&self) -> Result<String> {
+        let key = ::ring::hmac::Key::new(::ring::hmac::HMAC_SHA256, self.key()?);
+
+        let mut ctx = ::ring::hmac::Context::with_key(&key);
+        ctx.update(format!("{}\n", self.revision).as_bytes());
+        if self.opaque {
+            ctx.update(b"opaque\n");
+        }
+        for (name, digest) in &self.entries {
+            let line = match self.fingerprints.get(name) {
+                Some(fingerprint) => format!("{}\0{}\0{}\n", name, digest, fingerprint),
+                None => format!("{}\0{}\n", name, digest),
+            };
+            ctx.update(line.as_bytes());
+        }
+        Ok(BASE64.encode(ctx.sign().as_ref()))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Verification {
+    /// Whether the database matches its manifest (or is empty and has none.)
+    pub fn is_ok(&self) -> bool {
+        self.revision.is_none() || (self.authentic
+            && self.missing.is_empty()
+            && self.unexpected.is_empty()
+            && self.modified.is_empty())
+    }
+
+    /// Combine the verifications of two sections of the manifest.
+    pub(crate) fn merge(mut self, other: Self) -> Self {
+        self.authentic = (self.revision.is_none() || self.authentic) && other.authentic;
+        self.revision = Some(self.revision.unwrap_or(0) + other.revision.unwrap_or(0));
+        self.missing.extend(other.missing);
+        self.unexpected.extend(other.unexpected);
+        self.modified.extend(other.modified);
+        self
+    }
+}
+
+impl ::std::fmt::Display for Verification {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        let revision = match self.revision {
+            None => return write!(f, "no manifest, it will be created on the next change"),
+            Some(revision) => revision,
+        };
+
+        if self.is_ok() {
+            return write!(f, "ok, revision {}", revision);
+        }
+        write!(f, "manifest mismatch at revision {}", revision)?;
+        if !self.authentic {
+            write!(f, "\n  manifest was modified without the key")?;
+        }
+        for name in &self.missing {
+            write!(f, "\n  {}: deleted since the manifest was written", name)?;
+        }
+        for name in &self.unexpected {
+            write!(f, "\n  {}: added without updating the manifest", name)?;
+        }
+        for name in &self.modified {
+            write!(f, "\n  {}: changed or rolled back without updating the manifest", name)?;
+        }
+        Ok(())
+    }
+}
+
+impl Encrypted {
+    /// A hash of the encrypted secret, as recorded in the manifest.
+    fn digest(&self) -> String {
+        let mut ctx = ::ring::digest::Context::new(&::ring::digest::SHA256);
+        for field in &[&self.name, &self.secret, &self.salt, &self.cipher] {
+            ctx.update(field.as_bytes());
+            ctx.update(b"\0");
+        }
+        // Metadata came later, leave the digests of secrets without any unchanged.
+        if !self.description.is_empty() || !self.tags.is_empty() {
+            ctx.update(self.description.as_bytes());
+            ctx.update(b"\0");
+            ctx.update(self.tags.join("\n").as_bytes());
+            ctx.update(b"\0");
+        }
+        BASE64.encode(ctx.finish().as_ref())
+    }
+}
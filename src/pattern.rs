@@ -0,0 +1,53 @@
+use crate::prelude::*;
+
+impl Pattern {
+    /// Match the whole name against a glob, where `*` matches any string and `?` any character.
+    pub fn glob(glob: &str) -> Result<Self> {
+        let regex = glob.chars().map(|c| match c {
+            '*' => ".*".to_owned(),
+            '?' => ".".to_owned(),
+            c => ::regex::escape(&c.to_string()),
+        }).collect::<String>();
+        Self::regex(&format!("^{}$", regex))
+    }
+
+    /// Search the name for a regex, which is not anchored.
+    pub fn regex(regex: &str) -> Result<Self> {
+        Ok(Self {
+            regex: ::regex::Regex::new(regex).context("compiling pattern")?,
+            tags: vec![],
+            values: false,
+        })
+    }
+
+    /// Only match secrets tagged with `tag`.
+    pub fn with_tag(mut self, tag: String) -> Self {
+        self.tags.push(tag);
+        self
+    }
+
+    /// Match decrypted values as well, which requires decrypting the secrets.
+    pub fn with_values(mut self) -> Self {
+        self.values = true;
+        self
+    }
+
+    pub(crate) fn values(&self) -> bool {
+        self.values
+    }
+
+    /// Whether `secret`, which may not be decrypted yet, matches by name, description and tags.
+    pub(crate) fn matches_metadata(&self, secret: &Secret) -> bool {
+        self.matches_tags(secret) && (
+            self.regex.is_match(&secret.name) || self.regex.is_match(&secret.description)
+        )
+    }
+
+    pub(crate) fn matches_tags(&self, secret: &Secret) -> bool {
+        self.tags.iter().all(|tag| secret.tags.contains(tag))
+    }
+
+    pub(crate) fn matches_value(&self, secret: &Secret) -> bool {
+        self.matches_tags(secret) && self.regex.is_match(&secret.secret)
+    }
+}
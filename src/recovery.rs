@@ -0,0 +1,83 @@
+//! Break-glass recovery of the database password.
+//!
+//! The password is split into printable shares using [Shamir's secret sharing][1], any
+//! `threshold` of which are enough to rebuild it. Every share carries its own checksum so that
+//! typos are caught before recombination, and the password itself is checksummed to detect shares
+//! coming from different splits.
+//!
+//! [1]: https://en.wikipedia.org/wiki/Shamir%27s_Secret_Sharing
+
+use crate::prelude::*;
+use ::std::convert::TryFrom;
+
+const PREFIX: &str = "hips";
+const CHECKSUM_LEN: usize = 4;
+
+/// Split `password` into `shares` printable shares, any `threshold` of which can rebuild it.
+pub fn split(password: &str, threshold: u8, shares: u8) -> Result<Vec<String>> {
+    if threshold == 0 || threshold > shares {
+        return Err(Error::msg("threshold must be between 1 and the number of shares"));
+    }
+
+    let mut secret = checksum(password.as_bytes());
+    secret.extend(password.as_bytes());
+    Ok(::sharks::Sharks(threshold).dealer(&secret).take(shares as usize).map(|share| {
+        let share = Vec::from(&share);
+        format!("{}-{}-{}-{}", PREFIX, threshold, hex(&share), hex(&checksum(&share)))
+    }).collect())
+}
+
+/// Rebuild the password from at least `threshold` of the printable `shares`.
+pub fn combine(shares: &[String]) -> Result<String> {
+    let mut threshold = None;
+    let shares = shares.iter().map(|share| {
+        let (t, share) = parse(share).with_context(|| format!("parsing share {}", share))?;
+        match threshold.replace(t) {
+            Some(other) if other != t => Err(Error::msg("shares have different thresholds")),
+            _ => ::sharks::Share::try_from(&share[..]).map_err(Error::msg),
+        }
+    }).collect::<Result<Vec<_>>>()?;
+
+    let secret = ::sharks::Sharks(threshold.context("no shares provided")?)
+        .recover(&shares)
+        .map_err(|err| Error::msg(err.to_owned()))?;
+    let (sum, password) = secret.split_at(CHECKSUM_LEN.min(secret.len()));
+    if sum != &checksum(password)[..] {
+        return Err(Error::msg("shares do not belong to the same password"));
+    }
+    String::from_utf8(password.to_vec()).context("loading password as utf8")
+}
+
+fn parse(share: &str) -> Result<(u8, Vec<u8>)> {
+    let parts = share.trim().split('-').collect::<Vec<_>>();
+    if parts.len() != 4 || parts[0] != PREFIX {
+        return Err(Error::msg(format!("expected {}-THRESHOLD-SHARE-CHECKSUM", PREFIX)));
+    }
+
+    let threshold = parts[1].parse().context("parsing threshold")?;
+    let bytes = unhex(parts[2]).context("decoding share")?;
+    if unhex(parts[3]).context("decoding checksum")? != checksum(&bytes) {
+        return Err(Error::msg("checksum mismatch, share is probably mistyped"));
+    }
+    Ok((threshold, bytes))
+}
+
+fn checksum(data: &[u8]) -> Vec<u8> {
+    ::ring::digest::digest(&::ring::digest::SHA256, data).as_ref()[..CHECKSUM_LEN].to_vec()
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(data: &str) -> Result<Vec<u8>> {
+    if !data.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::msg("invalid hex digit"));
+    }
+    if !data.len().is_multiple_of(2) {
+        return Err(Error::msg("odd number of hex digits"));
+    }
+    (0..data.len()).step_by(2).map(|i| {
+        u8::from_str_radix(&data[i..i + 2], 16).context("invalid hex digit")
+    }).collect()
+}
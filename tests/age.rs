@@ -0,0 +1,256 @@
+//! Run the [age][1] encrypter against the age spec's test vectors (see the [CCTV][2] project.)
+//!
+//! Every vector is placed in a `Folder` database and loaded back through `Database::load`, the
+//! same way a secret written by the age tooling would be.
+//!
+//! [1]: https://age-encryption.org/v1
+//! [2]: https://github.com/C2SP/CCTV/tree/main/age
+
+use ::age::secrecy::ExposeSecret;
+use ::base64::engine::general_purpose::STANDARD as BASE64;
+use ::base64::Engine;
+use ::hips::{backends, encrypters, Backend, Database, Encrypted, Secret};
+use ::std::path::Path;
+
+mod common;
+use common::{secret, yaml};
+
+const VECTORS: &str = "tests/testdata/age";
+
+struct Vector {
+    expect: String,
+    payload: Option<String>,
+    identities: Vec<String>,
+    passphrases: Vec<String>,
+    armored: String,
+}
+
+impl Vector {
+    fn read(name: &str) -> Self {
+        let raw = ::std::fs::read(Path::new(VECTORS).join(name)).unwrap();
+        let split = raw.windows(2).position(|w| w == b"\n\n").unwrap();
+        let (header, body) = (::std::str::from_utf8(&raw[..split]).unwrap(), &raw[split + 2..]);
+
+        let mut vector = Self {
+            expect: String::new(),
+            payload: None,
+            identities: vec![],
+            passphrases: vec![],
+            armored: String::new(),
+        };
+        let mut armored = false;
+        for (key, value) in header.lines().filter_map(|line| line.split_once(": ")) {
+            match key {
+                "expect" => vector.expect = value.to_owned(),
+                "payload" => vector.payload = Some(value.to_owned()),
+                "identity" => vector.identities.push(value.to_owned()),
+                "passphrase" => vector.passphrases.push(value.to_owned()),
+                "armored" => armored = value == "yes",
+                _ => {}
+            }
+        }
+
+        vector.armored = if armored {
+            String::from_utf8(body.to_vec()).unwrap()
+        } else {
+            let encoded = BASE64.encode(body);
+            let lines = encoded.as_bytes().chunks(64).map(|chunk| {
+                ::std::str::from_utf8(chunk).unwrap()
+            }).collect::<Vec<_>>().join("\n");
+            format!("-----BEGIN AGE ENCRYPTED FILE-----\n{}\n-----END AGE ENCRYPTED FILE-----\n", lines)
+        };
+        vector
+    }
+
+    fn load(&self) -> ::anyhow::Result<Secret> {
+        let dir = ::tempfile::tempdir().unwrap();
+        ::std::fs::create_dir(dir.path().join("vector")).unwrap();
+        ::std::fs::write(dir.path().join("vector").join("secret"), &self.armored).unwrap();
+        ::std::fs::write(dir.path().join("vector").join("salt"), "").unwrap();
+
+        let encrypter = match self.passphrases.first() {
+            Some(passphrase) => encrypters::Age::with_passphrase(passphrase.clone()),
+            None => encrypters::Age::with_recipients("", &self.identities.join("\n")).unwrap(),
+        };
+        let db = Database::new(
+            Box::new(Unverified(backends::Folder::new(dir.path().to_owned()))),
+            Box::new(encrypter),
+        );
+        db.load("vector".to_owned())
+    }
+}
+
+/// A backend without integrity manifest, since vectors come from outside of hips.
+struct Unverified(backends::Folder);
+
+impl Backend for Unverified {
+    fn store(&mut self, encrypted: Encrypted) -> ::anyhow::Result<()> {
+        self.0.store(encrypted)
+    }
+    fn load(&self, name: String) -> ::anyhow::Result<Encrypted> {
+        self.0.load(name)
+    }
+    fn remove(&mut self, name: String) -> ::anyhow::Result<()> {
+        self.0.remove(name)
+    }
+    fn list(&self) -> ::anyhow::Result<Vec<Encrypted>> {
+        self.0.list()
+    }
+}
+
+fn sha256(data: &str) -> String {
+    ::ring::digest::digest(&::ring::digest::SHA256, data.as_bytes()).as_ref().iter().map(|b| {
+        format!("{:02x}", b)
+    }).collect()
+}
+
+#[test]
+fn test_vectors() {
+    let mut names = ::std::fs::read_dir(VECTORS).unwrap().map(|entry| {
+        entry.unwrap().file_name().into_string().unwrap()
+    }).collect::<Vec<_>>();
+    names.sort();
+
+    for name in names {
+        let vector = Vector::read(&name);
+        match (vector.expect.as_str(), vector.load()) {
+            ("success", Ok(secret)) => {
+                assert_eq!(Some(sha256(&secret.secret)), vector.payload, "{}", name)
+            }
+            ("success", Err(err)) => panic!("{}: expected success, got {:#}", name, err),
+            (expect, Ok(_)) => panic!("{}: expected {}, got success", name, expect),
+            (_, Err(_)) => {}
+        }
+    }
+}
+
+#[test]
+fn round_trip_passphrase() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let mut db = Database::new(
+        Box::new(backends::YAML::new(dir.path().join("secrets.yaml"))),
+        Box::new(encrypters::Age::with_passphrase("pw".to_owned())),
+    );
+    db.store(Secret {
+        name: "name".to_owned(),
+        secret: "secret".to_owned(),
+        ..Default::default()
+    }).unwrap();
+    assert_eq!(db.load("name".to_owned()).unwrap().secret, "secret");
+}
+
+#[test]
+fn round_trip_x25519() {
+    let identity = ::age::x25519::Identity::generate();
+    let recipient = identity.to_public().to_string();
+    let identity = identity.to_string().expose_secret().to_owned();
+
+    let dir = ::tempfile::tempdir().unwrap();
+    let mut db = Database::new(
+        Box::new(backends::Folder::new(dir.path().join("secrets"))),
+        Box::new(encrypters::Age::with_recipients(&recipient, &identity).unwrap()),
+    );
+    db.store(Secret {
+        name: "name".to_owned(),
+        secret: "secret".to_owned(),
+        ..Default::default()
+    }).unwrap();
+    assert_eq!(db.load("name".to_owned()).unwrap().secret, "secret");
+
+    let armored = ::std::fs::read_to_string(dir.path().join("secrets/name/secret")).unwrap();
+    assert!(armored.starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+}
+
+#[test]
+fn converts_ring_databases() {
+    let identity = ::age::x25519::Identity::generate();
+    let recipient = identity.to_public().to_string();
+    let identity = identity.to_string().expose_secret().to_owned();
+    let age = || Box::new(encrypters::Age::with_recipients(&recipient, &identity).unwrap());
+
+    let dir = ::tempfile::tempdir().unwrap();
+    let backend = || Box::new(backends::YAML::new(dir.path().join("secrets.yaml")));
+    let mut db = yaml(&dir.path().join("secrets.yaml"), "pw");
+    db.store(secret("name", "secret")).unwrap();
+    db.rotate(age()).unwrap();
+
+    let db = Database::new(backend(), age());
+    assert!(db.verify().unwrap().is_ok());
+    assert_eq!(db.load("name".to_owned()).unwrap().secret, "secret");
+    let ring = yaml(&dir.path().join("secrets.yaml"), "pw");
+    assert!(ring.load("name".to_owned()).is_err());
+}
+
+#[test]
+fn round_trip_ssh() {
+    let read = |name: &str| ::std::fs::read_to_string(Path::new("tests/testdata/ssh").join(name));
+    let recipients = read("recipients").unwrap();
+
+    let dir = ::tempfile::tempdir().unwrap();
+    let backend = || Box::new(backends::YAML::new(dir.path().join("secrets.yaml")));
+    let mut db = Database::new(
+        backend(),
+        Box::new(encrypters::Age::with_ssh_keys(&recipients, "", None).unwrap()),
+    );
+    db.store(Secret {
+        name: "name".to_owned(),
+        secret: "secret".to_owned(),
+        ..Default::default()
+    }).unwrap();
+
+    let plain = encrypters::Age::with_ssh_keys(&recipients, &read("plain").unwrap(), None);
+    let db = Database::new(backend(), Box::new(plain.unwrap()));
+    assert_eq!(db.load("name".to_owned()).unwrap().secret, "secret");
+
+    let locked = read("locked").unwrap();
+    assert!(encrypters::Age::with_ssh_keys(&recipients, &locked, None).is_err());
+    assert!(encrypters::Age::with_ssh_keys(&recipients, &locked, Some("bad".to_owned())).is_err());
+    let locked = encrypters::Age::with_ssh_keys(&recipients, &locked, Some("hunter2".to_owned()));
+    let db = Database::new(backend(), Box::new(locked.unwrap()));
+    assert_eq!(db.load("name".to_owned()).unwrap().secret, "secret");
+
+    // Ring databases are converted by rotating them.
+    let dir = ::tempfile::tempdir().unwrap();
+    let backend = || Box::new(backends::YAML::new(dir.path().join("secrets.yaml")));
+    let mut db = yaml(&dir.path().join("secrets.yaml"), "pw");
+    db.store(secret("name", "secret")).unwrap();
+    db.rotate(Box::new(encrypters::Age::with_ssh_keys(&recipients, "", None).unwrap())).unwrap();
+    let plain = encrypters::Age::with_ssh_keys(&recipients, &read("plain").unwrap(), None);
+    let db = Database::new(backend(), Box::new(plain.unwrap()));
+    assert!(db.verify().unwrap().is_ok());
+    assert_eq!(db.load("name".to_owned()).unwrap().secret, "secret");
+}
+
+#[test]
+fn parses_authorized_keys() {
+    let read = |name: &str| ::std::fs::read_to_string(Path::new("tests/testdata/ssh").join(name));
+    let key = read("recipients").unwrap().lines().next().unwrap().replace(" plain", "");
+    let recipients = [
+        format!(r#"command="ssh-agent -a \"/tmp/a b\"",no-pty {} plain ssh-rsa"#, key),
+        format!("from=\"10.0.0.1\"\t{}", key.replace(' ', "\t")),
+        format!("# ssh-ed25519 commented out\n{} user@ssh-host", key),
+    ];
+
+    for recipients in &recipients {
+        let dir = ::tempfile::tempdir().unwrap();
+        let backend = || Box::new(backends::YAML::new(dir.path().join("secrets.yaml")));
+        let writer = encrypters::Age::with_ssh_keys(recipients, "", None).unwrap();
+        Database::new(backend(), Box::new(writer)).store(secret("name", recipients)).unwrap();
+        let plain = encrypters::Age::with_ssh_keys(recipients, &read("plain").unwrap(), None);
+        let db = Database::new(backend(), Box::new(plain.unwrap()));
+        assert_eq!(&db.load("name".to_owned()).unwrap().secret, recipients);
+    }
+
+    let unsupported = [
+        "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBHFliOyIZs1gxGF3fm\
+            DxFykQhE88wy6AKDGFBfn0R6ZuvRmENABZQa9+pj9hMki+LX0qDJbmHTiWDbYv/cmFt/Q= ecdsa",
+        "no-touch-required sk-ssh-ed25519@openssh.com AAAAGnNrLXNzaC1lZDI1NTE5QG9wZW5zc2guY29tAA\
+            AAIAABAgMEBQYHCAkKCwwNDg8QERITFBUWFxgZGhscHR4fAAAABHNzaDo= yubikey",
+    ];
+    for recipients in &unsupported {
+        let err = encrypters::Age::with_ssh_keys(recipients, "", None).err().unwrap();
+        assert!(format!("{:#}", err).contains("unsupported key type"), "{:#}", err);
+    }
+    let unterminated = format!(r#"command="echo {}"#, key);
+    assert!(encrypters::Age::with_ssh_keys(&unterminated, "", None).is_err());
+}
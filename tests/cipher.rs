@@ -0,0 +1,49 @@
+use ::hips::encrypters::{Cipher, Ring};
+use ::hips::{backends, Database, Encrypted, Encrypter};
+
+mod common;
+use common::secret;
+
+#[test]
+fn round_trips_chacha20_poly1305() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets.yaml");
+    let open = |cipher| Database::new(
+        Box::new(backends::YAML::new(path.clone())),
+        Box::new(Ring::with_cipher("pw".to_owned(), cipher)),
+    );
+
+    let mut db = open(Cipher::Aes256Gcm);
+    db.store(secret("a", "1")).unwrap();
+    assert_eq!(db.cipher().unwrap(), Some("aes-256-gcm".to_owned()));
+    db.rotate(Box::new(Ring::with_cipher("pw".to_owned(), Cipher::ChaCha20Poly1305))).unwrap();
+    assert_eq!(db.cipher().unwrap(), Some("chacha20-poly1305".to_owned()));
+    assert!(!::std::fs::read_to_string(&path).unwrap().contains("aes-256-gcm"));
+
+    // The cipher is read from every secret, whichever the encrypter would use.
+    let mut db = open(Cipher::Aes256Gcm);
+    assert_eq!(db.load("a".to_owned()).unwrap().secret, "1");
+    db.store(secret("b", "2")).unwrap();
+    assert_eq!(db.cipher().unwrap(), Some("chacha20-poly1305".to_owned()));
+    let mut names = open(Cipher::ChaCha20Poly1305).list().unwrap().into_iter().map(|s| {
+        (s.name, s.secret)
+    }).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec![("a".to_owned(), "1".to_owned()), ("b".to_owned(), "2".to_owned())]);
+}
+
+#[test]
+fn decrypts_entries_without_cipher() {
+    // Secrets written before ciphers were selectable record none, and are AES256 GCM.
+    let ring = Ring::new("pw".to_owned());
+    let encrypted = ::serde_yaml::to_string(&ring.encrypt(secret("a", "1")).unwrap()).unwrap();
+    assert!(encrypted.contains("cipher: aes-256-gcm\n"));
+    let legacy = encrypted.replace("cipher: aes-256-gcm\n", "");
+    let legacy: Encrypted = ::serde_yaml::from_str(&legacy).unwrap();
+    assert_eq!(ring.decrypt(legacy.clone()).unwrap(), secret("a", "1"));
+
+    let chacha = Ring::with_cipher("pw".to_owned(), Cipher::ChaCha20Poly1305);
+    assert_eq!(chacha.decrypt(legacy).unwrap(), secret("a", "1"));
+    let tagged = encrypted.replace("aes-256-gcm", "chacha20-poly1305");
+    assert!(ring.decrypt(::serde_yaml::from_str(&tagged).unwrap()).is_err());
+}
@@ -0,0 +1,23 @@
+//! Helpers shared by the integration tests, each test binary using a subset of them.
+#![allow(dead_code)]
+
+use ::hips::{backends, encrypters, Database, Encrypter, Secret};
+use ::std::path::Path;
+
+pub fn secret(name: &str, secret: &str) -> Secret {
+    Secret::new(name.to_owned(), secret.to_owned())
+}
+
+pub fn ring(password: &str) -> Box<dyn Encrypter> {
+    Box::new(encrypters::Ring::new(password.to_owned()))
+}
+
+/// A YAML database at `path`, encrypted with the `Ring` encrypter.
+pub fn yaml(path: &Path, password: &str) -> Database {
+    Database::new(Box::new(backends::YAML::new(path.to_owned())), ring(password))
+}
+
+/// A folder database at `path`, encrypted with the `Ring` encrypter.
+pub fn folder(path: &Path, password: &str) -> Database {
+    Database::new(Box::new(backends::Folder::new(path.to_owned())), ring(password))
+}
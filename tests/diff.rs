@@ -0,0 +1,38 @@
+use ::hips::{backends, encrypters, Backend, Change, Encrypter};
+
+mod common;
+use common::{secret, yaml};
+
+#[test]
+fn compares_decrypted_values() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let open = |name: &str, password: &str| yaml(&dir.path().join(name), password);
+
+    let mut prod = open("prod.yaml", "prod");
+    prod.store(secret("same", "1")).unwrap();
+    prod.store(secret("changed", "2")).unwrap();
+    prod.store(secret("removed", "3")).unwrap();
+    let mut staging = open("staging.yaml", "staging");
+    staging.store(secret("same", "1")).unwrap();
+    staging.store(secret("changed", "4")).unwrap();
+    staging.store(secret("added", "5")).unwrap();
+
+    assert_eq!(prod.diff(&staging).unwrap(), vec![
+        Change::Added(secret("added", "5")),
+        Change::Changed(secret("changed", "2"), secret("changed", "4")),
+        Change::Removed(secret("removed", "3")),
+    ]);
+    assert!(prod.diff(&open("prod.yaml", "prod")).unwrap().is_empty());
+    assert!(prod.diff(&open("staging.yaml", "prod")).is_err());
+
+    // Databases written before manifests existed have none, they are compared all the same.
+    let mut backend = backends::YAML::new(dir.path().join("old.yaml"));
+    let encrypter = encrypters::Ring::new("prod".to_owned());
+    backend.store(encrypter.encrypt(secret("same", "1")).unwrap()).unwrap();
+    let old = open("old.yaml", "prod");
+    assert!(old.verify().is_err());
+    assert_eq!(old.diff(&prod).unwrap(), vec![
+        Change::Added(secret("changed", "2")),
+        Change::Added(secret("removed", "3")),
+    ]);
+}
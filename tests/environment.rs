@@ -0,0 +1,18 @@
+//! Templates reading the environment, in their own test binary: setting variables while other
+//! tests read them in parallel threads is not safe.
+
+mod common;
+use common::{secret, yaml};
+
+#[test]
+fn exposes_environment_without_hips_variables() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let mut db = yaml(&dir.path().join("secrets.yaml"), "pw");
+    db.store(secret("prod/db/password", "hunter2")).unwrap();
+    ::std::env::set_var("APP_TEST_HOST", "db.example.com");
+    ::std::env::set_var("HIPS_TEST_PASSWORD", "pw");
+
+    let template = "{env.APP_TEST_HOST} {groups.prod.db.password}".to_owned();
+    assert_eq!(db.template(template).unwrap(), "db.example.com hunter2");
+    assert!(db.template("{env.HIPS_TEST_PASSWORD}".to_owned()).is_err());
+}
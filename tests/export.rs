@@ -0,0 +1,57 @@
+use ::hips::exporters::{export, Format};
+use ::hips::{importers, Secret};
+
+mod common;
+use common::secret;
+
+fn secrets() -> Vec<Secret> {
+    vec![
+        secret("api-key", "k"),
+        secret("db/password", "p\"$w\\\n\tx"),
+        secret("db/user", "admin"),
+    ]
+}
+
+#[test]
+fn exports_formats() {
+    let dotenv = export(&secrets(), Format::Dotenv, None, None).unwrap();
+    assert_eq!(dotenv, "API_KEY=\"k\"\nDB_PASSWORD=\"p\\\"\\$w\\\\\\n\\tx\"\nDB_USER=\"admin\"\n");
+    assert_eq!(importers::parse(&dotenv, importers::Format::Dotenv).unwrap(), vec![
+        secret("API_KEY", "k"),
+        secret("DB_PASSWORD", "p\"$w\\\n\tx"),
+        secret("DB_USER", "admin"),
+    ]);
+
+    assert!(export(&secrets(), Format::DockerEnv, None, None).is_err());
+    assert_eq!(
+        export(&[secret("a/b", "it's $raw")], Format::DockerEnv, None, None).unwrap(),
+        "A_B=it's $raw\n",
+    );
+    assert!(export(&[secret("a/b", ""), secret("a_b", "")], Format::Dotenv, None, None).is_err());
+
+    for (format, import) in &[
+        (Format::Json, importers::Format::Json),
+        (Format::Yaml, importers::Format::Yaml),
+    ] {
+        let exported = export(&secrets(), *format, None, None).unwrap();
+        assert_eq!(importers::parse(&exported, *import).unwrap(), secrets());
+    }
+    assert!(export(&[secret("a", ""), secret("a/b", "")], Format::Json, None, None).is_err());
+}
+
+#[test]
+fn exports_kubernetes_secrets() {
+    assert!(export(&secrets(), Format::K8sSecret, None, None).is_err());
+    assert_eq!(export(&secrets(), Format::K8sSecret, Some("app"), Some("prod")).unwrap(), "\
+apiVersion: v1
+kind: Secret
+metadata:
+  name: app
+  namespace: prod
+type: Opaque
+data:
+  api-key: aw==
+  db_password: cCIkd1wKCXg=
+  db_user: YWRtaW4=
+");
+}
@@ -0,0 +1,133 @@
+use ::hips::{encrypters, Database};
+
+mod common;
+use common::{secret, yaml};
+
+#[test]
+fn merges_entries() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let open = |name: &str| yaml(&dir.path().join(name), "pw");
+    let read = |name: &str| ::std::fs::read_to_string(dir.path().join(name)).unwrap();
+
+    let mut base = open("base.yaml");
+    base.store(secret("shared", "1")).unwrap();
+    base.store(secret("removed", "2")).unwrap();
+    base.store(secret("conflict", "3")).unwrap();
+    for copy in &["ours", "theirs"] {
+        for file in &["yaml", "manifest.yaml"] {
+            let (from, to) = (format!("base.{}", file), format!("{}.{}", copy, file));
+            ::std::fs::copy(dir.path().join(from), dir.path().join(to)).unwrap();
+        }
+    }
+
+    let mut ours = open("ours.yaml");
+    ours.store(secret("ours", "4")).unwrap();
+    ours.store(secret("conflict", "5")).unwrap();
+    let mut theirs = open("theirs.yaml");
+    theirs.store(secret("theirs", "6")).unwrap();
+    theirs.store(secret("conflict", "7")).unwrap();
+    theirs.remove("removed".to_owned()).unwrap();
+
+    let merge = ::hips::git::merge(&read("base.yaml"), &read("ours.yaml"), &read("theirs.yaml"))
+        .unwrap();
+    assert_eq!(merge.conflicts, vec!["conflict".to_owned()]);
+    ::std::fs::write(dir.path().join("merged.yaml"), merge.content).unwrap();
+    let (base, ours, theirs) = (
+        read("base.manifest.yaml"), read("ours.manifest.yaml"), read("theirs.manifest.yaml"),
+    );
+    let merge = ::hips::git::merge_manifest(&open("ours.yaml"), &base, &ours, &theirs).unwrap();
+    assert_eq!(merge.conflicts, vec!["conflict".to_owned()]);
+    ::std::fs::write(dir.path().join("merged.manifest.yaml"), merge.content).unwrap();
+
+    let merged = open("merged.yaml");
+    assert_eq!(merged.verify().unwrap().revision, Some(7));
+    let mut names = merged.list().unwrap().into_iter().map(|s| (s.name, s.secret))
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec![
+        ("conflict".to_owned(), "5".to_owned()),
+        ("ours".to_owned(), "4".to_owned()),
+        ("shared".to_owned(), "1".to_owned()),
+        ("theirs".to_owned(), "6".to_owned()),
+    ]);
+
+    let forged = ours.replace("revision: 5", "revision: 9");
+    assert_ne!(forged, ours);
+    assert!(::hips::git::merge_manifest(&open("ours.yaml"), &base, &forged, &theirs).is_err());
+}
+
+#[test]
+fn converts_for_diffs() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let open = |path: &str, password: &str| Database::open(
+        dir.path().join(path),
+        Box::new(encrypters::Ring::new(password.to_owned())),
+    ).unwrap();
+    let read = |name: &str| ::std::fs::read_to_string(dir.path().join(name)).unwrap();
+    let mut db = open("secrets.yaml", "pw");
+    db.store(secret("b", "value")).unwrap();
+    db.store(secret("a", "other")).unwrap();
+
+    let fingerprints = ::hips::git::textconv(&db, &read("secrets.yaml"), false).unwrap();
+    let fingerprint = |view: &str, name: &str| view.lines().find_map(|line| {
+        line.strip_prefix(&format!("{}: hmac:", name)).map(str::to_owned)
+    }).unwrap();
+    let a = fingerprint(&fingerprints, "a");
+    assert_eq!(a.len(), 16);
+    assert_ne!(a, fingerprint(&fingerprints, "b"));
+    let plain = ::hips::git::textconv(&db, &read("secrets.yaml"), true).unwrap();
+    assert_eq!(plain, "a: \"other\"\nb: \"value\"\n");
+    let locked = ::hips::git::textconv(&open("secrets.yaml", "wrong"), &read("secrets.yaml"), false);
+    assert_eq!(locked.unwrap(), "a: <cannot decrypt>\nb: <cannot decrypt>\n");
+
+    // Fingerprints follow the secrets, not their ciphertexts, and resolve opaque names.
+    db.store(secret("a", "other")).unwrap();
+    db.set_opaque_names(true).unwrap();
+    let view = ::hips::git::textconv(&db, &read("secrets.yaml"), false).unwrap();
+    assert_eq!(view, fingerprints);
+    db.store(secret("a", "changed")).unwrap();
+    let view = ::hips::git::textconv(&db, &read("secrets.yaml"), false).unwrap();
+    assert_ne!(fingerprint(&view, "a"), a);
+
+    // Folder databases are diffed through their manifest, which records the fingerprints.
+    let mut folder = open("secrets", "pw");
+    folder.store(secret("a", "other")).unwrap();
+    let manifest = ::hips::git::textconv(&folder, &read("secrets/.manifest.yaml"), false).unwrap();
+    let lines = manifest.lines().map(|l| l.split(':').next().unwrap()).collect::<Vec<_>>();
+    assert_eq!(lines, vec!["revision", "a"]);
+    let a = fingerprint(&manifest, "a");
+    folder.store(secret("a", "other")).unwrap();
+    let view = ::hips::git::textconv(&folder, &read("secrets/.manifest.yaml"), false).unwrap();
+    assert_eq!(fingerprint(&view, "a"), a);
+    let unavailable = open("missing", "pw");
+    let view = ::hips::git::textconv(&unavailable, &read("secrets/.manifest.yaml"), false).unwrap();
+    assert_eq!(fingerprint(&view, "a"), a);
+}
+
+#[test]
+fn helps_with_credentials() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let mut db = yaml(&dir.path().join("secrets.yaml"), "pw");
+    let mut helper = |action: &str, input: &str| {
+        ::hips::git::credential(&mut db, action, input, ::hips::git::CREDENTIAL_SCHEME).unwrap()
+    };
+
+    let request = "protocol=https\nhost=example.com\n\n";
+    assert_eq!(helper("get", request), "");
+    helper("store", "protocol=https\nhost=example.com\nusername=bot\npassword=token\n\n");
+    assert_eq!(helper("get", request), "username=bot\npassword=token\n");
+    assert_eq!(helper("get", "protocol=https\nhost=example.com\nusername=other\n"), "");
+    assert_eq!(helper("get", "protocol=https\nhost=other.com\n"), "");
+    assert_eq!(helper("get", "protocol=http\nhost=example.com\n"), "");
+    assert_eq!(helper("unknown", request), "");
+    helper("erase", "protocol=https\nhost=example.com\nusername=other\n");
+    assert_eq!(helper("get", request), "username=bot\npassword=token\n");
+    helper("erase", request);
+    assert_eq!(helper("get", request), "");
+
+    assert!(db.list_names("").unwrap().is_empty());
+    assert!(::hips::git::credential(&mut db, "get", "", "{path}").is_err());
+
+    db.store(secret("git/https/example.com/password", "token\nhost=evil.com")).unwrap();
+    assert!(::hips::git::credential(&mut db, "get", request, "git/{protocol}/{host}").is_err());
+}
@@ -0,0 +1,129 @@
+use ::hips::importers::{import, parse, read, read_with, Conflicts, Format};
+use ::hips::Secret;
+use ::std::path::Path;
+
+mod common;
+use common::{folder, secret, yaml};
+
+const TESTDATA: &str = "tests/testdata";
+
+#[test]
+fn parses_formats() {
+    let dotenv = "\
+        # comment\n\
+        export PLAIN = some value # trailing comment\n\
+        DOUBLE=\"first\n\
+        second \\\"quoted\\\" \\$HOME\\n\"\n\
+        SINGLE='literal \\n $HOME' # comment\n\
+        EMPTY=\n";
+    assert_eq!(parse(dotenv, Format::Dotenv).unwrap(), vec![
+        secret("PLAIN", "some value"),
+        secret("DOUBLE", "first\nsecond \"quoted\" $HOME\n"),
+        secret("SINGLE", "literal \\n $HOME"),
+        secret("EMPTY", ""),
+    ]);
+    assert!(parse("A=\"unterminated\n", Format::Dotenv).is_err());
+    assert!(parse("A=\"closed\" trailing\n", Format::Dotenv).is_err());
+    assert!(parse("NOT AN ASSIGNMENT\n", Format::Dotenv).is_err());
+    assert!(parse("A=1\nexport A=2\n", Format::Dotenv).is_err());
+
+    assert_eq!(parse("A='kept' # as is\n", Format::Env).unwrap(), vec![
+        secret("A", "'kept' # as is"),
+    ]);
+
+    let json = r#"{"db": {"user": "admin", "port": 5432}, "debug": true}"#;
+    let expected = vec![
+        secret("db/port", "5432"),
+        secret("db/user", "admin"),
+        secret("debug", "true"),
+    ];
+    assert_eq!(parse(json, Format::Json).unwrap(), expected);
+    let yaml = "db:\n  user: admin\n  port: 5432\ndebug: true\n";
+    assert_eq!(parse(yaml, Format::Yaml).unwrap(), expected);
+    assert!(parse(r#"{"list": [1, 2]}"#, Format::Json).is_err());
+    assert!(parse(r#"{"db/user": "a", "db": {"user": "b"}}"#, Format::Json).is_err());
+    assert!(parse("just a string", Format::Yaml).is_err());
+}
+
+#[test]
+fn handles_conflicts() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let mut db = yaml(&dir.path().join("secrets.yaml"), "pw");
+    db.store(secret("a", "old")).unwrap();
+    let secrets = || vec![secret("a", "new"), secret("b", "2")];
+
+    assert!(import(&mut db, secrets(), Conflicts::Fail, false).is_err());
+    assert_eq!(db.list_names("").unwrap(), vec!["a".to_owned()]);
+
+    let summary = import(&mut db, secrets(), Conflicts::Overwrite, true).unwrap();
+    assert_eq!((summary.added, summary.overwritten), (vec!["b".to_owned()], vec!["a".to_owned()]));
+    assert_eq!(db.list_names("").unwrap(), vec!["a".to_owned()]);
+
+    let summary = import(&mut db, secrets(), Conflicts::Skip, false).unwrap();
+    assert_eq!((summary.added, summary.skipped), (vec!["b".to_owned()], vec!["a".to_owned()]));
+    assert_eq!(db.load("a".to_owned()).unwrap().secret, "old");
+
+    import(&mut db, secrets(), Conflicts::Overwrite, false).unwrap();
+    assert_eq!(db.load("a".to_owned()).unwrap().secret, "new");
+    assert!(import(&mut db, vec![secret("bad/", "")], Conflicts::Overwrite, false).is_err());
+}
+
+fn round_trip(secrets: Vec<Secret>) -> Vec<Secret> {
+    let dir = ::tempfile::tempdir().unwrap();
+    let mut db = folder(dir.path(), "pw");
+    import(&mut db, secrets, Conflicts::Fail, false).unwrap();
+    let mut secrets = db.list().unwrap();
+    secrets.sort_by(|a, b| a.name.cmp(&b.name));
+    secrets
+}
+
+#[test]
+fn imports_pass_stores() {
+    let expected = vec![
+        secret("email/work", "p@ss w0rd"),
+        secret("web/github", "hunter2\nlogin: octocat"),
+    ];
+    let export = Path::new(TESTDATA).join("pass/export");
+    assert_eq!(Format::guess(&export), Some(Format::Pass));
+    assert_eq!(round_trip(read(&export, Format::Pass).unwrap()), expected);
+
+    // Decrypting the store itself needs gpg, skip that half where it is not installed.
+    let home = ::tempfile::tempdir().unwrap();
+    let status = ::std::process::Command::new("gpg")
+        .env("GNUPGHOME", home.path())
+        .args(["--quiet", "--batch", "--import"])
+        .arg(Path::new(TESTDATA).join("pass/key.asc"))
+        .status();
+    let status = match status {
+        Ok(status) => status,
+        Err(error) if error.kind() == ::std::io::ErrorKind::NotFound => {
+            eprintln!("gpg not found, skipping the pass store import");
+            return;
+        }
+        Err(error) => panic!("{}", error),
+    };
+    assert!(status.success());
+    let store = read_with(&Path::new(TESTDATA).join("pass/store"), Format::Pass, Some(home.path()));
+    let _ = ::std::process::Command::new("gpgconf")
+        .env("GNUPGHOME", home.path())
+        .args(["--kill", "gpg-agent"])
+        .status();
+    assert_eq!(round_trip(store.unwrap()), expected);
+}
+
+#[test]
+fn imports_sops_exports() {
+    let expected = vec![
+        secret("api_token", "tok_123"),
+        secret("database/password", "s3cr3t"),
+        secret("database/port", "5432"),
+        secret("database/user", "admin"),
+    ];
+    let sops = Path::new(TESTDATA).join("sops");
+    assert_eq!(round_trip(read(&sops.join("decrypted.yaml"), Format::Sops).unwrap()), expected);
+    assert_eq!(round_trip(read(&sops.join("decrypted.json"), Format::Sops).unwrap()), expected);
+    assert!(read(&sops.join("encrypted.yaml"), Format::Sops).is_err());
+    assert_eq!(parse("sops:\n  version: 3\n", Format::Sops).unwrap(), vec![
+        secret("sops/version", "3"),
+    ]);
+}
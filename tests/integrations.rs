@@ -0,0 +1,79 @@
+use ::hips::{integrations, Database};
+
+mod common;
+use common::{secret, yaml};
+
+fn database(dir: &::tempfile::TempDir) -> Database {
+    let mut db = yaml(&dir.path().join("secrets.yaml"), "pw");
+    db.store(secret("prod/db/password", "hunter2")).unwrap();
+    db.store(secret("prod/db/user", "admin")).unwrap();
+    db.store(secret("token", "t")).unwrap();
+    db
+}
+
+#[test]
+fn answers_terraform_queries() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let db = database(&dir);
+    let query = |query: &str| integrations::terraform_data(&db, query);
+
+    assert_eq!(
+        query(r#"{"prefix": "prod/db/", "names": "token, prod/db/user"}"#).unwrap(),
+        r#"{"password":"hunter2","prod/db/user":"admin","token":"t","user":"admin"}"#,
+    );
+    assert!(query("{}").is_err());
+    assert!(query(r#"{"names": "missing"}"#).is_err());
+    assert!(query(r#"{"other": "token"}"#).is_err());
+    assert!(query(r#"{"names": ["token"]}"#).is_err());
+    assert!(query(r#"{"names": "token,token"}"#).is_err());
+    assert!(query(r#"{"prefix": "", "names": "token"}"#).is_err());
+}
+
+#[test]
+fn prints_aws_credentials() {
+    use integrations::aws_credentials as credentials;
+    let dir = ::tempfile::tempdir().unwrap();
+    let mut db = database(&dir);
+    db.store(secret("expiration", "2026-10-18T12:00:00Z")).unwrap();
+
+    assert_eq!(
+        credentials(&db, "prod/db/user", "prod/db/password", None, None).unwrap(),
+        r#"{"Version":1,"AccessKeyId":"admin","SecretAccessKey":"hunter2"}"#,
+    );
+    assert_eq!(
+        credentials(&db, "prod/db/user", "prod/db/password", Some("token"), Some("expiration"))
+            .unwrap(),
+        concat!(
+            r#"{"Version":1,"AccessKeyId":"admin","SecretAccessKey":"hunter2","SessionToken":"t","#,
+            r#""Expiration":"2026-10-18T12:00:00Z"}"#,
+        ),
+    );
+    assert!(credentials(&db, "missing", "prod/db/password", None, None).is_err());
+    assert!(credentials(&db, "prod/db/user", "prod/db/password", Some("token"), None).is_err());
+}
+
+#[test]
+fn helps_docker_with_credentials() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let mut db = database(&dir);
+    let mut helper = |action: &str, input: &str| {
+        integrations::docker_credential(&mut db, action, input).unwrap()
+    };
+
+    let url = "https://index.docker.io/v1/";
+    assert_eq!(helper("get", url), None);
+    assert_eq!(helper("list", ""), Some("{}".to_owned()));
+    let credentials = r#"{"ServerURL":"https://index.docker.io/v1/","Username":"me","Secret":"s"}"#;
+    assert_eq!(helper("store", credentials), Some(String::new()));
+    assert_eq!(helper("get", &format!("{}\n", url)), Some(credentials.to_owned()));
+    assert_eq!(helper("list", ""), Some(r#"{"https://index.docker.io/v1/":"me"}"#.to_owned()));
+    assert_eq!(helper("erase", url), Some(String::new()));
+    assert_eq!(helper("erase", url), None);
+    assert_eq!(helper("get", url), None);
+
+    assert!(integrations::docker_credential(&mut db, "unknown", "").is_err());
+    assert_eq!(db.list_names("").unwrap().len(), 3);
+
+    db.store(secret("docker/index.docker.io/v1/secret", "s")).unwrap();
+    assert_eq!(integrations::docker_credential(&mut db, "get", url).unwrap(), None);
+}
@@ -0,0 +1,132 @@
+use ::hips::{backends, encrypters, Database};
+
+mod common;
+use common::{folder, secret, yaml};
+
+#[test]
+fn detects_deleted_and_rolled_back_entries() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets.yaml");
+    let open = || yaml(&path, "pw");
+
+    let mut db = open();
+    db.store(secret("a", "1")).unwrap();
+    db.store(secret("b", "2")).unwrap();
+    let before = ::std::fs::read_to_string(&path).unwrap();
+    db.store(secret("a", "3")).unwrap();
+    assert!(db.verify().unwrap().is_ok());
+    assert_eq!(db.verify().unwrap().revision, Some(3));
+
+    ::std::fs::write(&path, &before).unwrap();
+    let verification = db.verify().unwrap();
+    assert_eq!(verification.modified, vec!["a".to_owned()]);
+    assert!(db.load("b".to_owned()).is_err());
+
+    let mut db = folder(&dir.path().join("secrets"), "pw");
+    db.store(secret("a", "1")).unwrap();
+    db.store(secret("b", "2")).unwrap();
+    ::std::fs::remove_dir_all(dir.path().join("secrets").join("a")).unwrap();
+    assert_eq!(db.verify().unwrap().missing, vec!["a".to_owned()]);
+    assert!(db.list().is_err());
+
+    db.reset_manifest().unwrap();
+    assert!(db.verify().unwrap().is_ok());
+    assert_eq!(db.list().unwrap().len(), 1);
+}
+
+#[test]
+fn rejects_forged_manifest() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets.yaml");
+    let mut db = yaml(&path, "pw");
+    db.store(secret("a", "1")).unwrap();
+
+    let manifest = dir.path().join("secrets.manifest.yaml");
+    let forged = ::std::fs::read_to_string(&manifest).unwrap().replace("revision: 1", "revision: 9");
+    ::std::fs::write(&manifest, forged).unwrap();
+    assert!(!db.verify().unwrap().authentic);
+}
+
+#[test]
+fn hides_names_when_opaque() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets.yaml");
+    let mut db = yaml(&path, "pw");
+    db.store(secret("acme_merger", "1")).unwrap();
+    db.set_opaque_names(true).unwrap();
+    db.store(secret("other", "2")).unwrap();
+
+    let content = ::std::fs::read_to_string(&path).unwrap();
+    assert!(!content.contains("acme_merger") && !content.contains("other"));
+    assert_eq!(db.load("acme_merger".to_owned()).unwrap().secret, "1");
+    assert_eq!(db.list().unwrap().len(), 2);
+    db.rotate(Box::new(encrypters::Ring::new("pw2".to_owned()))).unwrap();
+    db.remove("other".to_owned()).unwrap();
+    assert_eq!(db.load("acme_merger".to_owned()).unwrap().secret, "1");
+
+    // The mode is kept with the key, losing or forging the manifest cannot turn it off.
+    let manifest = dir.path().join("secrets.manifest.yaml");
+    let content = ::std::fs::read_to_string(&manifest).unwrap();
+    ::std::fs::write(&manifest, content.replace("opaque: true\n", "")).unwrap();
+    assert_eq!(db.load("acme_merger".to_owned()).unwrap().secret, "1");
+    ::std::fs::remove_file(&manifest).unwrap();
+    assert!(db.load("acme_merger".to_owned()).is_err());
+    db.reset_manifest().unwrap();
+    assert!(!::std::fs::read_to_string(&path).unwrap().contains("acme_merger"));
+    assert_eq!(db.load("acme_merger".to_owned()).unwrap().secret, "1");
+
+    db.set_opaque_names(false).unwrap();
+    assert!(::std::fs::read_to_string(&path).unwrap().contains("acme_merger"));
+    assert!(db.verify().unwrap().is_ok());
+}
+
+#[test]
+fn fails_closed_without_manifest() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets.yaml");
+    let mut db = yaml(&path, "pw");
+    assert!(db.verify().unwrap().is_ok());
+    db.store(secret("a", "1")).unwrap();
+
+    let manifest = dir.path().join("secrets.manifest.yaml");
+    ::std::fs::remove_file(&manifest).unwrap();
+    assert!(db.verify().is_err());
+    assert!(db.load("a".to_owned()).is_err());
+    assert!(db.store(secret("b", "2")).is_err());
+    assert!(!manifest.exists());
+
+    db.reset_manifest().unwrap();
+    assert!(db.verify().unwrap().is_ok());
+    let content = ::std::fs::read_to_string(&path).unwrap();
+    ::std::fs::write(&path, content.split("- name: .manifest").next().unwrap()).unwrap();
+    assert!(db.verify().is_err());
+    assert!(db.list().is_err());
+
+    // Databases which predate manifests have to be accepted once.
+    ::std::fs::remove_file(&manifest).unwrap();
+    ::std::fs::write(&path, "- name: a\n  secret: ''\n").unwrap();
+    assert!(db.verify().is_err());
+    db.reset_manifest().unwrap();
+    assert!(db.verify().unwrap().is_ok());
+}
+
+#[test]
+fn leaves_write_only_changes_to_accept() {
+    let identity = ::age::x25519::Identity::generate();
+    let recipient = identity.to_public().to_string();
+    let identity = ::age::secrecy::ExposeSecret::expose_secret(&identity.to_string()).to_owned();
+
+    let dir = ::tempfile::tempdir().unwrap();
+    let open = |identity: &str| Database::new(
+        Box::new(backends::YAML::new(dir.path().join("secrets.yaml"))),
+        Box::new(encrypters::Age::with_recipients(&recipient, identity).unwrap()),
+    );
+    open("").store(secret("a", "1")).unwrap();
+    let mut db = open(&identity);
+    assert_eq!(db.load("a".to_owned()).unwrap().secret, "1");
+
+    open("").store(secret("b", "2")).unwrap();
+    assert_eq!(db.verify().unwrap().unexpected, vec!["b".to_owned()]);
+    db.reset_manifest().unwrap();
+    assert_eq!(db.load("b".to_owned()).unwrap().secret, "2");
+}
@@ -0,0 +1,205 @@
+use ::hips::{backends, encrypters, Database, Secret};
+
+mod common;
+use common::{folder, secret, yaml};
+
+#[test]
+fn nests_folders_and_renames_subtrees() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets");
+    let mut db = folder(&path, "pw");
+    db.store(secret("prod", "1")).unwrap();
+    db.store(secret("prod/db/password", "2")).unwrap();
+    db.store(secret("production_notes", "3")).unwrap();
+    assert!(path.join("prod/db/password/secret").is_file());
+    assert!(db.store(secret("prod//db", "4")).is_err());
+    assert!(db.store(secret("../escape", "4")).is_err());
+
+    assert_eq!(db.list_names("prod/").unwrap(), vec!["prod", "prod/db/password"]);
+    assert_eq!(db.list_scope("prod/db").unwrap()[0].secret, "2");
+    assert!(db.verify().unwrap().is_ok());
+
+    assert_eq!(db.rename("prod", "staging").unwrap(), 2);
+    assert!(db.rename("staging", "staging/old").is_err());
+    assert_eq!(db.list_names("").unwrap(), vec![
+        "production_notes", "staging", "staging/db/password",
+    ]);
+    assert!(!path.join("prod").exists());
+
+    db.remove("staging".to_owned()).unwrap();
+    assert_eq!(db.load("staging/db/password".to_owned()).unwrap().secret, "2");
+    db.remove("staging/db/password".to_owned()).unwrap();
+    assert!(!path.join("staging").exists());
+}
+
+#[test]
+fn validates_names() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets");
+    let mut db = folder(&path, "pw");
+    db.store(secret(".aws/credentials", "1")).unwrap();
+    db.store(secret("app/.env", "2")).unwrap();
+    assert_eq!(db.list_names("").unwrap(), vec![".aws/credentials", "app/.env"]);
+    assert_eq!(db.load(".aws/credentials".to_owned()).unwrap().secret, "1");
+
+    // Folders hold the files of their secret, nested secrets cannot be named after them.
+    db.store(secret("a", "1")).unwrap();
+    db.store(secret("tags", "1")).unwrap();
+    for name in &["a/secret", "b/tags", "c/salt", "d/e/cipher", "f/description", ".manifest.yaml"] {
+        assert!(db.store(secret(name, "3")).is_err(), "{}", name);
+    }
+    db.store(Secret { tags: vec!["x".to_owned()], ..secret("b", "2") }).unwrap();
+    db.store(secret("c", "2")).unwrap();
+    assert!(!path.join("b/tags").is_dir() && !path.join("c/salt").is_dir());
+    assert_eq!(db.list_names("").unwrap(), vec![
+        ".aws/credentials", "a", "app/.env", "b", "c", "tags",
+    ]);
+
+    for name in &[".", "..", "a/./b", "a/../b", ".manifest", "app/.manifest", "a/"] {
+        assert!(db.store(secret(name, "3")).is_err(), "{}", name);
+        assert!(db.load(name.to_string()).is_err(), "{}", name);
+        assert!(db.remove(name.to_string()).is_err(), "{}", name);
+    }
+    ::std::fs::create_dir_all(dir.path().join("outside")).unwrap();
+    ::std::fs::write(dir.path().join("outside/secret"), "x").unwrap();
+    ::std::fs::write(dir.path().join("outside/salt"), "x").unwrap();
+    assert!(db.load("../outside".to_owned()).is_err());
+    assert!(db.remove("../outside".to_owned()).is_err());
+    assert!(dir.path().join("outside/secret").is_file());
+
+    // The backend checks the names it uses as paths, whoever calls it.
+    let mut folder = backends::Folder::new(path);
+    assert!(::hips::Backend::load(&folder, "../outside".to_owned()).is_err());
+    assert!(::hips::Backend::remove(&mut folder, "../outside".to_owned()).is_err());
+    assert!(dir.path().join("outside/secret").is_file());
+    assert!(db.verify().unwrap().is_ok());
+}
+
+#[test]
+fn encrypts_scopes_under_their_own_keys() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets.yaml");
+    let open = |admin: bool| {
+        let ring = |pw: &str| Some(Box::new(encrypters::Ring::new(pw.to_owned())) as Box<_>);
+        let encrypter = encrypters::Scoped::new(if admin { ring("admin") } else { None })
+            .with_scope("staging/".to_owned(), ring("staging"))
+            .with_scope("prod/".to_owned(), if admin { ring("prod") } else { None });
+        Database::new(Box::new(backends::YAML::new(path.clone())), Box::new(encrypter))
+    };
+
+    let mut db = open(true);
+    db.store(secret("prod/db", "1")).unwrap();
+    db.store(secret("staging/db", "2")).unwrap();
+    db.store(secret("other", "3")).unwrap();
+
+    let mut db = open(false);
+    let unlocked = db.scopes().into_iter().map(|s| s.unlocked).collect::<Vec<_>>();
+    assert_eq!(unlocked, vec![false, true, false]);
+    assert!(db.verify().unwrap().is_ok());
+    assert_eq!(db.load("staging/db".to_owned()).unwrap().secret, "2");
+    assert!(db.load("prod/db".to_owned()).is_err());
+    assert!(db.load("other".to_owned()).is_err());
+    db.store(secret("staging/api", "4")).unwrap();
+    assert!(db.store(secret("prod/api", "5")).is_err());
+    assert_eq!(open(true).list().unwrap().len(), 4);
+
+    // Each scope signs its own section of the manifest, others cannot accept changes to it.
+    let content = ::std::fs::read_to_string(&path).unwrap();
+    let prod = content.find("- name: prod/db").unwrap();
+    let end = content[prod + 1..].find("- name").map(|end| prod + 1 + end).unwrap();
+    ::std::fs::write(&path, format!("{}{}", &content[..prod], &content[end..])).unwrap();
+    db.reset_manifest().unwrap();
+    assert!(db.verify().unwrap().is_ok());
+    assert_eq!(open(true).verify().unwrap().missing, vec!["prod/db".to_owned()]);
+    assert!(open(true).list().is_err());
+}
+
+#[test]
+fn rotates_into_scopes() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets.yaml");
+    let ring = |pw: &str| Box::new(encrypters::Ring::new(pw.to_owned()));
+    let scoped = |prod: bool| {
+        let ring = |pw: &str| Some(ring(pw) as Box<dyn ::hips::Encrypter>);
+        Box::new(encrypters::Scoped::new(ring("pw"))
+            .with_scope("staging/".to_owned(), ring("staging"))
+            .with_scope("prod/".to_owned(), if prod { ring("prod") } else { None }))
+    };
+    let backend = || Box::new(backends::YAML::new(path.clone()));
+
+    let mut db = Database::new(backend(), ring("pw"));
+    db.store(secret("prod/db", "1")).unwrap();
+    db.store(secret("staging/db", "2")).unwrap();
+    db.store(secret("other", "3")).unwrap();
+    // The secrets were not encrypted under the keys of their scopes yet.
+    assert!(Database::new(backend(), scoped(true)).list().is_err());
+
+    db.rotate(scoped(true)).unwrap();
+    assert!(db.verify().unwrap().is_ok());
+    let db = Database::new(backend(), scoped(false));
+    assert!(db.verify().unwrap().is_ok());
+    assert_eq!(db.load("staging/db".to_owned()).unwrap().secret, "2");
+    assert_eq!(db.load("other".to_owned()).unwrap().secret, "3");
+    assert!(db.load("prod/db".to_owned()).is_err());
+    let manifest = ::std::fs::read_to_string(dir.path().join("secrets.manifest.yaml")).unwrap();
+    assert!(manifest.contains("prod/:") && manifest.contains("staging/:"));
+
+    // And back to a single key, the sections of the scopes being dropped.
+    let mut db = Database::new(backend(), scoped(true));
+    db.rotate(ring("pw")).unwrap();
+    assert!(db.verify().unwrap().is_ok());
+    assert_eq!(db.list().unwrap().len(), 3);
+    let manifest = ::std::fs::read_to_string(dir.path().join("secrets.manifest.yaml")).unwrap();
+    assert!(!manifest.contains("scopes:"));
+    assert!(!::std::fs::read_to_string(&path).unwrap().contains("prod/.manifest"));
+}
+
+#[test]
+fn refuses_opaque_names_with_scopes() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let backend = || Box::new(backends::YAML::new(dir.path().join("secrets.yaml")));
+    let ring = |pw: &str| Box::new(encrypters::Ring::new(pw.to_owned()));
+    let scoped = || Box::new(encrypters::Scoped::new(Some(ring("pw")))
+        .with_scope("prod/".to_owned(), Some(ring("prod"))));
+
+    let mut db = Database::new(backend(), scoped());
+    db.store(secret("prod/db", "1")).unwrap();
+    assert!(db.set_opaque_names(true).is_err());
+    assert_eq!(db.load("prod/db".to_owned()).unwrap().secret, "1");
+
+    // Nor can opaque databases be written to with scopes, prod/ secrets would not use its key.
+    db.rotate(ring("pw")).unwrap();
+    db.set_opaque_names(true).unwrap();
+    let mut db = Database::new(backend(), scoped());
+    assert!(db.store(secret("prod/api", "2")).is_err());
+    assert!(db.store(secret("other", "3")).is_err());
+    assert_eq!(db.list().unwrap().len(), 1);
+    assert!(db.verify().unwrap().is_ok());
+}
+
+#[test]
+fn finds_secrets() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let mut db = yaml(&dir.path().join("secrets.yaml"), "pw");
+    db.store(secret("aws_access_key_id", "AKIA")).unwrap();
+    db.store(secret("aws_secret", "old-host.example.com")).unwrap();
+    db.store(Secret {
+        name: "db".to_owned(),
+        secret: "postgres://old-host".to_owned(),
+        description: "AWS RDS database".to_owned(),
+        tags: vec!["prod".to_owned()],
+    }).unwrap();
+
+    let find = |pattern: ::hips::Pattern| db.find(&pattern).unwrap();
+    assert_eq!(find(::hips::Pattern::glob("aws_*").unwrap()), vec![
+        "aws_access_key_id", "aws_secret",
+    ]);
+    assert_eq!(find(::hips::Pattern::regex("(?i)aws").unwrap()), vec![
+        "aws_access_key_id", "aws_secret", "db",
+    ]);
+    assert_eq!(find(::hips::Pattern::glob("*").unwrap().with_tag("prod".to_owned())), vec!["db"]);
+    assert!(find(::hips::Pattern::regex("old-host").unwrap()).is_empty());
+    assert_eq!(find(::hips::Pattern::regex("old-host").unwrap().with_values()), vec![
+        "aws_secret", "db",
+    ]);
+}
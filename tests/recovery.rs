@@ -0,0 +1,25 @@
+use ::hips::recovery::{combine, split};
+
+#[test]
+fn round_trip() {
+    let shares = split("correct horse battery staple", 3, 5).unwrap();
+    assert_eq!(shares.len(), 5);
+    assert_eq!(combine(&shares[..3]).unwrap(), "correct horse battery staple");
+    assert_eq!(combine(&shares[2..]).unwrap(), "correct horse battery staple");
+    assert!(combine(&shares[..2]).is_err());
+}
+
+#[test]
+fn mistyped_and_mixed_shares() {
+    let mut shares = split("password", 2, 3).unwrap();
+    let other = split("pa55word", 2, 3).unwrap();
+    assert!(combine(&[shares[0].clone(), other[1].clone()]).is_err());
+
+    shares[0] = shares[0].replacen("-0", "-9", 1);
+    assert!(combine(&shares[..2]).is_err());
+    assert!(split("password", 4, 3).is_err());
+
+    let (head, tail) = shares[1].rsplit_once('-').unwrap();
+    assert!(combine(&[shares[2].clone(), format!("{}-é{}", head, &tail[2..])]).is_err());
+    assert!(combine(&[shares[2].clone(), format!("{}-{}", head, &tail[1..])]).is_err());
+}
@@ -0,0 +1,165 @@
+use ::hips::{engines, Database, Secret};
+
+mod common;
+use common::{folder, secret, yaml};
+
+fn database(dir: &::std::path::Path) -> Database {
+    let mut db = yaml(&dir.join("secrets.yaml"), "pw");
+    db.store(secret("quote", "it's \"on\"")).unwrap();
+    db.store(secret("encoded", "aGVsbG8=")).unwrap();
+    db
+}
+
+#[test]
+fn formats_secrets() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let db = database(dir.path());
+    let render = |template: &str| db.template(template.to_owned()).unwrap();
+
+    assert_eq!(render("{map.quote|shellquote}"), r#"'it'\''s "on"'"#);
+    assert_eq!(render("{map.quote|json}"), r#""it's \"on\"""#);
+    assert_eq!(render("{map.quote|urlencode}"), "it%27s%20%22on%22");
+    assert_eq!(render("{map.encoded|base64decode}"), "hello");
+    assert_eq!(render("{map.encoded|base64}"), "YUdWc2JHOD0=");
+    assert!(render("{map.quote|htpasswd}").starts_with("$2y$"));
+    assert_eq!(
+        render("{map.encoded|sha256}"),
+        "333d6b3a3c1f5db6c9bdda5939b136986d170f4649172a68368d54ecb44c2ff2",
+    );
+}
+
+#[test]
+fn fails_on_invalid_input() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let db = database(dir.path());
+    assert!(db.template("{map.quote|base64decode}".to_owned()).is_err());
+    assert!(db.template("{list|capitalize}".to_owned()).is_err());
+}
+
+#[test]
+fn renders_directories() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let db = database(dir.path());
+    let (src, dest) = (dir.path().join("src"), dir.path().join("dest"));
+    ::std::fs::create_dir_all(src.join(".aws")).unwrap();
+    ::std::fs::write(src.join("app.env.tmpl"), "QUOTE={map.quote|shellquote}\n").unwrap();
+    ::std::fs::write(src.join(".aws/credentials.tmpl"), "key={map.encoded}\n").unwrap();
+    ::std::fs::write(src.join("README"), "not a template").unwrap();
+
+    let outputs: Vec<::std::path::PathBuf> = vec![".aws/credentials".into(), "app.env".into()];
+    assert_eq!(db.render(&src, &dest, 0o600, true).unwrap(), outputs);
+    assert!(!dest.exists());
+    assert_eq!(db.render(&src, &dest, 0o600, false).unwrap(), outputs);
+    assert_eq!(::std::fs::read_to_string(dest.join(".aws/credentials")).unwrap(), "key=aGVsbG8=\n");
+    assert!(!dest.join("README").exists());
+    assert!(db.render(&src, &dest, 0o600, true).unwrap().is_empty());
+
+    #[cfg(unix)]
+    {
+        use ::std::os::unix::fs::PermissionsExt;
+        let mode = || ::std::fs::metadata(dest.join("app.env")).unwrap().permissions().mode();
+        assert_eq!(mode() & 0o777, 0o600);
+        let outputs: Vec<::std::path::PathBuf> = vec!["app.env".into()];
+        let permissions = ::std::fs::Permissions::from_mode(0o644);
+        ::std::fs::set_permissions(dest.join("app.env"), permissions).unwrap();
+        assert_eq!(db.render(&src, &dest, 0o600, true).unwrap(), outputs);
+        assert_eq!(db.render(&src, &dest, 0o600, false).unwrap(), outputs);
+        assert_eq!(mode() & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn checks_references_strictly() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let db = database(dir.path());
+    let check = |template: &str, prefix| db.check_template(template.to_owned(), prefix);
+
+    let err = check("{map.quote} {map.qoute|json} {{ if map.missing }}x{{ endif }}", None);
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "template references missing secrets: missing, qoute",
+    );
+    assert_eq!(check("{map.quote}", Some("")).unwrap(), vec!["encoded".to_owned()]);
+    assert!(check("{map.quote}", Some("q")).unwrap().is_empty());
+    assert!(check("{{ for s in list }}{s.secret}{{ endfor }}", Some("")).unwrap().is_empty());
+}
+
+#[test]
+fn finds_referenced_secrets() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let db = database(dir.path());
+    // The secrets a template does not reference are neither decrypted nor used.
+    let unused = |template: &str| db.check_template(template.to_owned(), Some("")).unwrap();
+
+    assert_eq!(unused("{map.encoded}"), vec!["quote".to_owned()]);
+    assert_eq!(unused("{meta.quote.description}"), vec!["encoded".to_owned()]);
+    assert_eq!(unused("{{ if map.quote }}{map.quote|json}{{ endif }}"), vec!["encoded".to_owned()]);
+    assert!(unused("{{ for s in list }}{s.name}{{ endfor }}").is_empty());
+    assert!(unused("{{ with map as m }}{m.quote}{{ endwith }}").is_empty());
+    assert!(unused("{% set m=map %}{{ m.quote }}").is_empty());
+    assert!(unused("{% with m=(map) %}{{ m['quote'] }}{% endwith %}").is_empty());
+    assert!(unused("{{ map['quote'] }}").is_empty());
+
+    let jinja = engines::Jinja::new(None);
+    let template = "{% set m=map %}{{ m.encoded }}";
+    assert_eq!(db.template_with(&jinja, template.to_owned()).unwrap(), "aGVsbG8=");
+}
+
+#[test]
+fn exposes_metadata_and_groups() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let mut db = yaml(&dir.path().join("secrets.yaml"), "pw");
+    db.store(Secret {
+        name: "prod/db/password".to_owned(),
+        secret: "hunter2".to_owned(),
+        description: "Primary database".to_owned(),
+        tags: vec!["db".to_owned(), "prod".to_owned()],
+    }).unwrap();
+
+    let render = |template: &str| db.template(template.to_owned()).unwrap();
+    assert_eq!(render("{groups.prod.db.password}"), "hunter2");
+    let tags = "{{ for t in s.tags }}{t} {{ endfor }}";
+    let template = format!("{{{{ for s in list }}}}{{s.description}}: {}{{{{ endfor }}}}", tags);
+    assert_eq!(render(&template), "Primary database: db prod ");
+    assert!(db.check_template("{groups.staging.db}".to_owned(), None).is_err());
+
+    let mut db = folder(&dir.path().join("secrets"), "pw");
+    db.store(Secret { name: "db".to_owned(), tags: vec!["a".to_owned()], ..Default::default() })
+        .unwrap();
+    assert_eq!(db.load("db".to_owned()).unwrap().tags, vec!["a".to_owned()]);
+}
+
+#[test]
+fn renders_jinja_templates() {
+    let dir = ::tempfile::tempdir().unwrap();
+    let db = database(dir.path());
+    let jinja = engines::Jinja::new(None);
+    let render = |template: &str| db.template_with(&jinja, template.to_owned());
+
+    assert_eq!(render("{{ map.quote | shellquote }}").unwrap(), r#"'it'\''s "on"'"#);
+    let all = "{% for s in list %}{{ s.name | capitalize }}{{ ',' if not loop.last }}{% endfor %}";
+    assert_eq!(render(all).unwrap(), "QUOTE,ENCODED");
+    assert!(render("{{ map.qoute }}").is_err());
+    assert!(render("{{ map.quote | base64decode }}").is_err());
+
+    let (src, dest) = (dir.path().join("src"), dir.path().join("dest"));
+    ::std::fs::create_dir_all(&src).unwrap();
+    ::std::fs::write(src.join("_export.j2"), "export {{ name }}={{ value | shellquote }}").unwrap();
+    let template = "{% with name = 'ENCODED', value = map.encoded %}\
+        {% include '_export.j2' %}{% endwith %}";
+    ::std::fs::write(src.join("app.env.j2"), template).unwrap();
+    let outputs = db.render(&src, &dest, 0o600, false).unwrap();
+    assert_eq!(outputs, vec![::std::path::PathBuf::from("app.env")]);
+    let output = ::std::fs::read_to_string(dest.join("app.env")).unwrap();
+    assert_eq!(output, "export ENCODED='aGVsbG8='");
+
+    // Templates are rendered as written, double quotes and backslashes included.
+    let jinja = engines::Jinja::new(Some(src));
+    let template = r#"{% with name = "A\"B", value = map.encoded %}{% include "_export.j2" %}"#;
+    let template = format!("{}{{% endwith %}}", template);
+    assert!(db.check_template(template.clone(), Some("")).unwrap().is_empty());
+    assert_eq!(db.template_with(&jinja, template).unwrap(), r#"export A"B='aGVsbG8='"#);
+    let template = r#"{map.encoded} "quoted" \n"#;
+    let output = db.template_with(&engines::TinyTemplate, template.to_owned()).unwrap();
+    assert_eq!(output, r#"aGVsbG8= "quoted" \n"#);
+}